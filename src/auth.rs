@@ -13,26 +13,57 @@ use oauth2::{
 use reqwest::{self, Url};
 use serde::{Deserialize, Serialize};
 
+const SCOPES: [&str; 3] = [
+    "esi-markets.structure_markets.v1",
+    "esi-search.search_structures.v1",
+    "esi-universe.read_structures.v1",
+];
+/// Only requested when fees are calculated from skills and standings of the character.
+const FEE_SCOPES: [&str; 2] = [
+    "esi-skills.read_skills.v1",
+    "esi-characters.read_standings.v1",
+];
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Auth {
     pub token: StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>,
     pub expiration_date: DateTime<Utc>,
+    /// Scopes the token was granted with.
+    #[serde(default)]
+    pub scopes: Vec<String>,
 }
 
 impl Auth {
-    pub async fn load_or_request_token(config: &AuthConfig) -> Self {
+    /// `character_fees` adds scopes for reading skills and standings of the character.
+    pub async fn load_or_request_token(config: &AuthConfig, character_fees: bool) -> Self {
         let path = "cache/auth";
-        let mut data = cached_data::load_or_create_json_async(path, false, None, || async {
-            let token = Self::request_new(config).await;
+        let scopes = SCOPES
+            .iter()
+            .chain(FEE_SCOPES.iter().filter(|_| character_fees))
+            .copied()
+            .collect::<Vec<_>>();
+        let scopes = &scopes;
+        let request = || async {
+            let token = Self::request_new(config, scopes).await;
             let expiration_date =
                 Utc::now() + chrono::Duration::from_std(token.expires_in().unwrap()).unwrap();
             Ok(Auth {
                 token,
                 expiration_date,
+                scopes: scopes.iter().map(|x| x.to_string()).collect(),
             })
-        })
-        .await
-        .unwrap();
+        };
+        let mut data = cached_data::load_or_create_json_async(path, false, None, request)
+            .await
+            .unwrap();
+
+        // token from an older version lacks scopes added since
+        if scopes.iter().any(|x| !data.scopes.iter().any(|y| y == x)) {
+            log::info!("Cached token lacks required scopes, requesting a new one.");
+            data = cached_data::load_or_create_json_async(path, true, None, request)
+                .await
+                .unwrap();
+        }
 
         // if expired use refresh token
         if data.expiration_date < Utc::now() {
@@ -48,6 +79,7 @@ impl Auth {
             data = Auth {
                 token,
                 expiration_date,
+                scopes: data.scopes,
             };
 
             cached_data::load_or_create_json_async(path, true, None, || {
@@ -63,13 +95,8 @@ impl Auth {
 
     async fn request_new(
         config: &AuthConfig,
+        scopes: &[&str],
     ) -> StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType> {
-        let scopes = vec![
-            "esi-markets.structure_markets.v1",
-            "esi-search.search_structures.v1",
-            "esi-universe.read_structures.v1",
-        ];
-
         let client = create_client(config);
 
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
@@ -77,12 +104,13 @@ impl Auth {
         // Generate the full authorization URL.
         let (auth_url, csrf_token) = client
             .authorize_url(CsrfToken::new_random)
-            .add_scopes(scopes.into_iter().map(|s| Scope::new(s.to_string())))
+            .add_scopes(scopes.iter().map(|s| Scope::new(s.to_string())))
             .set_pkce_challenge(pkce_challenge)
             .url();
 
-        println!("Go to this url:");
-        println!("{}", auth_url);
+        // stdout is for the results
+        eprintln!("Go to this url:");
+        eprintln!("{}", auth_url);
 
         let mut str = None;
 
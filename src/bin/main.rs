@@ -1,32 +1,50 @@
 use std::{collections::HashMap, io::Read};
 
-use chrono::Duration;
-use futures::{stream, StreamExt};
+use chrono::{Duration, Utc};
+use clap::ArgMatches;
+use futures::{future::join_all, stream, StreamExt};
+use itertools::Itertools;
 
 use oauth2::TokenResponse;
 use rust_eveonline_esi::apis::configuration::Configuration;
 
 use term_table::{row::Row, table_cell::TableCell, TableBuilder, TableStyle};
-use tokio::join;
 
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use unusable_eve_tradeworks_lib::{
     auth::Auth,
-    cached_data::{self},
+    cached_data::{self, ResponseCache},
+    cassette::{Cassette, CassetteProxy},
     cli,
     config::{AuthConfig, Config},
     consts::{self, BUFFER_UNORDERED},
     datadump_service::DatadumpService,
-    error::Result,
+    error::{Error, Result},
+    fees::CharacterFees,
     good_items::{
+        ranking::{make_table_ranking, rank, RankBy, RankedItem},
+        report::FilterReport,
         sell_buy::{get_good_items_sell_buy, make_table_sell_buy},
         sell_sell::{get_good_items_sell_sell, make_table_sell_sell},
         sell_sell_zkb::{get_good_items_sell_sell_zkb, make_table_sell_sell_zkb},
     },
-    item_type::{SystemMarketsItem, SystemMarketsItemData, TypeDescription},
+    item_type::SystemMarketsItemData,
     logger,
-    requests::service::EsiRequestsService,
-    zkb::{killmails::KillmailService, zkb_requests::ZkbRequestsService},
+    market_data::{load_market_data, route_pairs, MarketLocation},
+    market_store::MarketStore,
+    order_ext::OrderIterExt,
+    output::OutputFormat,
+    requests::{
+        fixture::{FixtureMarketDataSource, RecordingMarketDataSource},
+        market_data_source::MarketDataSource,
+        service::EsiRequestsService,
+    },
+    zkb::{
+        killmails::{ItemFrequencies, KillmailService},
+        zkb_requests::{ZkbRequestsService, ZKB_API_URL},
+    },
+    MarketScope, Station, StationIdData,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,20 +63,82 @@ async fn main() -> Result<()> {
     run().await
 }
 
+/// NPC stations are resolved from the datadump, structures through ESI.
+/// Resolved ids are cached for each station config.
+async fn find_station(
+    market: &dyn MarketDataSource,
+    data_service: &DatadumpService,
+    station: &Station,
+    character_id: i32,
+    cache_dir: &str,
+    refresh: bool,
+) -> Result<StationIdData> {
+    if let Some(ids) = station.known_ids() {
+        return Ok(ids);
+    }
+
+    let res = cached_data::load_or_create_json_async(
+        cached_data::cache_path(
+            cache_dir,
+            "stations",
+            station.cache_key(),
+            consts::STATION_SCHEMA_VERSION,
+            "json",
+        ),
+        refresh,
+        // structures can be unanchored or lose their market
+        Some(Duration::days(consts::STATION_CACHE_DAYS)),
+        || async {
+            if station.is_citadel() {
+                Ok(market
+                    .find_region_id_station(station.clone(), character_id)
+                    .await?)
+            } else if let Some(station_id) = station.station_id {
+                data_service.find_station_by_id(station_id)
+            } else {
+                data_service.find_station(station.name.as_str())
+            }
+        },
+    )
+    .await;
+
+    // main only prints debug representation of errors
+    if let Err(e @ Error::StationResolve(_)) = &res {
+        log::error!("{}", e);
+    }
+    res
+}
+
 async fn run() -> Result<()> {
     std::fs::create_dir_all("cache/")?;
 
     let cli_args = cli::matches();
 
-    let quiet = cli_args.is_present(cli::QUIET);
+    let output = cli_args
+        .value_of(cli::OUTPUT)
+        .and_then(OutputFormat::from_name)
+        .unwrap_or(OutputFormat::Table);
+    // logs would break machine readable output
+    let quiet = cli_args.is_present(cli::QUIET) || output != OutputFormat::Table;
     let file_loud = cli_args.is_present(cli::FILE_LOUD);
     logger::setup_logger(quiet, file_loud)?;
 
+    if let Some((cli::CACHE, args)) = cli_args.subcommand() {
+        return cache_command(args);
+    }
+
     let config_file_name = cli_args.value_of(cli::CONFIG).unwrap_or("config.json");
     let config = Config::from_file_json(config_file_name)?;
 
-    let program_config = AuthConfig::from_file("auth.json");
-    let auth = Auth::load_or_request_token(&program_config).await;
+    let fixture_path = cli_args.value_of(cli::FIXTURE);
+    let record_fixture_path = cli_args.value_of(cli::RECORD_FIXTURE);
+    let record_http_path = cli_args.value_of(cli::RECORD_HTTP);
+    let replay_http_path = cli_args.value_of(cli::REPLAY_HTTP);
+
+    let offline = fixture_path.is_some() || replay_http_path.is_some();
+
+    // replayed data must not end up in the real cache
+    let cache_dir = if offline { "cache/offline" } else { "cache" };
 
     let mut esi_config = Configuration {
         client: reqwest::ClientBuilder::new()
@@ -68,9 +148,59 @@ async fn run() -> Result<()> {
             .unwrap(),
         ..Default::default()
     };
-    esi_config.oauth_access_token = Some(auth.token.access_token().secret().clone());
 
-    let esi_requests = EsiRequestsService::new(&esi_config);
+    let cassette_proxy = if let Some(path) = replay_http_path {
+        Some(CassetteProxy::replay(Cassette::from_file_json(path)?)?)
+    } else if record_http_path.is_some() {
+        Some(CassetteProxy::record(
+            esi_config.client.clone(),
+            vec![
+                ("esi".to_string(), esi_config.base_path.clone()),
+                ("zkb".to_string(), ZKB_API_URL.to_string()),
+            ],
+        )?)
+    } else {
+        None
+    };
+    if let Some(proxy) = &cassette_proxy {
+        esi_config.base_path = proxy.url("esi");
+    }
+    let zkb_base_url = cassette_proxy
+        .as_ref()
+        .map_or(ZKB_API_URL.to_string(), |x| x.url("zkb"));
+
+    // offline runs don't need a character
+    let character_id = if let Some(character_id) = cassette_proxy
+        .as_ref()
+        .filter(|_| replay_http_path.is_some())
+        .and_then(|x| x.character_id())
+    {
+        character_id
+    } else if fixture_path.is_none() {
+        let program_config = AuthConfig::from_file("auth.json");
+        let auth = Auth::load_or_request_token(&program_config, config.character_fees).await;
+        esi_config.oauth_access_token = Some(auth.token.access_token().secret().clone());
+
+        // TODO: dangerous plese don't use in production
+        let character_info = jsonwebtoken::dangerous_insecure_decode::<CharacterInfo>(
+            auth.token.access_token().secret(),
+        )
+        .unwrap()
+        .claims;
+        let character_id = character_info
+            .sub
+            .split(':')
+            .nth(2)
+            .unwrap()
+            .parse()
+            .unwrap();
+        if let Some(proxy) = &cassette_proxy {
+            proxy.set_character_id(character_id);
+        }
+        character_id
+    } else {
+        0
+    };
 
     let path_to_datadump = cached_data::load_or_create_json_async(
         "cache/datadump.json",
@@ -90,7 +220,7 @@ async fn run() -> Result<()> {
             decompressor.read_to_end(&mut contents).unwrap();
 
             let path = "cache/datadump.db".to_string();
-            std::fs::write(&path, contents)?;
+            cached_data::write_atomic(&path, &contents)?;
             Ok(path)
         },
     )
@@ -101,204 +231,563 @@ async fn run() -> Result<()> {
         rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
     )?;
     let data_service = DatadumpService::new(db);
+    let route_graph = data_service.route_graph()?;
 
-    // TODO: dangerous plese don't use in production
-    let character_info = jsonwebtoken::dangerous_insecure_decode::<CharacterInfo>(
-        auth.token.access_token().secret(),
-    )
-    .unwrap()
-    .claims;
-    let character_id = character_info
-        .sub
-        .split(':')
-        .nth(2)
-        .unwrap()
-        .parse()
-        .unwrap();
-
-    let force_refresh = cli_args.is_present(cli::FORCE_REFRESH);
+    // recording has to see every request and replayed data doesn't expire
+    let force_refresh = cli_args.is_present(cli::FORCE_REFRESH)
+        || record_fixture_path.is_some()
+        || record_http_path.is_some()
+        || offline;
     let force_no_refresh = cli_args.is_present(cli::FORCE_NO_REFRESH);
+    let refresh_orders = force_refresh || cli_args.is_present(cli::REFRESH_ORDERS);
+    let refresh_history = force_refresh || cli_args.is_present(cli::REFRESH_HISTORY);
 
-    let mut pairs: Vec<SystemMarketsItemData> = {
-        let config = &config;
-        let esi_requests = &esi_requests;
-        let source_region = esi_requests
-            .find_region_id_station(config.source.clone(), character_id)
-            .await
-            .unwrap();
-
-        let dest_region = esi_requests
-            .find_region_id_station(config.destination.clone(), character_id)
-            .await
-            .unwrap();
+    // cassettes have to see every request and recorded fixtures every response in full
+    let response_cache = if record_http_path.is_some()
+        || replay_http_path.is_some()
+        || record_fixture_path.is_some()
+    {
+        ResponseCache::disabled()
+    } else {
+        ResponseCache::load(
+            format!("{}/responses.rmp", cache_dir),
+            refresh_orders || refresh_history,
+        )
+    };
+    // replayed data must not end up among downloaded history
+    let store = if offline {
+        MarketStore::open_in_memory()?
+    } else {
+        MarketStore::open(format!("{}/market.db", cache_dir))?
+    };
+    // unchanged history is taken from the store, a new store doesn't have it
+    if store.is_created() {
+        response_cache.evict("/history/");
+    }
+    let esi_requests = EsiRequestsService::new(
+        &esi_config,
+        &response_cache,
+        &config.retry,
+        &route_graph,
+        config.route_preference,
+        &config.avoid_systems,
+    );
+    let fixture = fixture_path
+        .map(FixtureMarketDataSource::from_file_json)
+        .transpose()?;
+    let live_market: &dyn MarketDataSource = match &fixture {
+        Some(fixture) => fixture,
+        None => &esi_requests,
+    };
+    let recording = record_fixture_path.map(|_| RecordingMarketDataSource::new(live_market));
+    let market: &dyn MarketDataSource = match &recording {
+        Some(recording) => recording,
+        None => live_market,
+    };
 
-        // all item type ids
-        let all_types = cached_data::load_or_create_json_async(
-            "cache/all_types.json",
+    let character_fees = if config.character_fees {
+        let fees = market.get_character_fees(character_id).await?;
+        log::info!("Sales tax from character skills: {:.4}", fees.sales_tax());
+        Some(fees)
+    } else {
+        None
+    };
+    let sales_tax = character_fees
+        .as_ref()
+        .map_or(config.sales_tax, |x| x.sales_tax());
+
+    let routes = config.routes();
+    let mut route_stations = Vec::new();
+    for route in &routes {
+        let mut sources = Vec::new();
+        for source in route.source.markets() {
+            sources.push(
+                find_station(
+                    market,
+                    &data_service,
+                    &source.station,
+                    character_id,
+                    cache_dir,
+                    force_refresh,
+                )
+                .await?,
+            );
+        }
+        let destination = find_station(
+            market,
+            &data_service,
+            &route.destination,
+            character_id,
+            cache_dir,
             force_refresh,
-            Some(Duration::days(7)),
-            || async {
-                let all_types = esi_requests.get_all_item_types(source_region.region_id);
-                let all_types_dest = esi_requests.get_all_item_types(dest_region.region_id);
-                let (all_types, all_types_dest) = join!(all_types, all_types_dest);
-                let (mut all_types, all_types_dest) = (all_types?, all_types_dest?);
-
-                all_types.extend(all_types_dest);
-                all_types.sort_unstable();
-                all_types.dedup();
-                Ok(all_types)
-            },
         )
         .await?;
+        route_stations.push((sources, destination));
+    }
+    let mut locations = Vec::new();
+    for (route, (sources, destination)) in routes.iter().zip(&route_stations) {
+        for &station in sources {
+            locations.push(MarketLocation {
+                station,
+                scope: MarketScope::Station,
+                systems: Vec::new(),
+            });
+        }
+        locations.push(MarketLocation {
+            station: *destination,
+            scope: route.destination_scope,
+            systems: data_service.area_systems(destination.system_id, route.destination_scope)?,
+        });
+    }
 
-        let all_type_descriptions: HashMap<i32, Option<TypeDescription>> =
-            cached_data::load_or_create_async(
-                "cache/all_type_descriptions.rmp",
-                force_refresh,
-                Some(Duration::days(7)),
-                || async {
-                    let res = stream::iter(all_types.clone())
-                        .map(|id| {
-                            let esi_requests = &esi_requests;
-                            async move {
-                                let req_res = esi_requests.get_item_stuff(id).await?;
-
-                                Ok((id, req_res.map(|x| x.into())))
-                            }
-                        })
-                        .buffer_unordered(BUFFER_UNORDERED)
-                        .collect::<Vec<Result<_>>>()
-                        .await
-                        .into_iter()
-                        .collect::<Result<Vec<_>>>()?
-                        .into_iter()
-                        .collect();
-
-                    Ok(res)
-                },
-            )
-            .await?;
+    // types of all regions are used for everything else
+    let regions = locations
+        .iter()
+        .map(|x| x.station.region_id)
+        .sorted_unstable()
+        .dedup()
+        .collect::<Vec<_>>();
+    let regions_key = regions.iter().join("-");
+
+    // all item type ids
+    let all_types = cached_data::load_or_create_json_async(
+        cached_data::cache_path(
+            cache_dir,
+            "types",
+            &regions_key,
+            consts::TYPE_IDS_SCHEMA_VERSION,
+            "json",
+        ),
+        force_refresh,
+        Some(Duration::days(7)),
+        || async {
+            let all_types = join_all(regions.iter().map(|&x| market.get_all_item_types(x)))
+                .await
+                .into_iter()
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let mut all_types = all_types.into_iter().flatten().collect::<Vec<_>>();
+            all_types.sort_unstable();
+            all_types.dedup();
+            Ok(all_types)
+        },
+    )
+    .await?;
 
-        let source_history = cached_data::load_or_create_async(
-            format!("cache/{}.rmp", config.source.name),
-            force_refresh,
-            Some(Duration::hours(config.refresh_timeout_hours)),
-            || async { Ok(esi_requests.history(&all_types, source_region).await?) },
-        );
-        let dest_history = cached_data::load_or_create_async(
-            format!("cache/{}.rmp", config.destination.name),
-            force_refresh,
-            Some(Duration::hours(config.refresh_timeout_hours)),
-            || async { Ok(esi_requests.history(&all_types, dest_region).await?) },
-        );
+    // descriptions rarely change, only missing and week old ones are downloaded
+    let mut all_type_descriptions = if force_refresh {
+        HashMap::new()
+    } else {
+        store.type_descriptions(Utc::now() - Duration::days(7))?
+    };
+    let missing_types = all_types
+        .iter()
+        .copied()
+        .filter(|x| !all_type_descriptions.contains_key(x))
+        .collect::<Vec<_>>();
+    if !missing_types.is_empty() {
+        log::info!("Downloading {} type descriptions...", missing_types.len());
+        let downloaded = stream::iter(missing_types)
+            .map(|id| async move {
+                let req_res = market.get_type_description(id).await?;
+
+                Ok((id, req_res))
+            })
+            .buffer_unordered(BUFFER_UNORDERED)
+            .collect::<Vec<Result<_>>>()
+            .await
+            .into_iter()
+            .collect::<Result<HashMap<_, _>>>()?;
+        store.save_type_descriptions(&downloaded)?;
+        all_type_descriptions.extend(downloaded);
+    }
 
-        let (source_history, dest_history) = join!(source_history, dest_history);
-        let (source_history, dest_history) = (source_history?, dest_history?);
+    let market_data = load_market_data(
+        market,
+        &store,
+        &all_types,
+        &locations,
+        refresh_orders,
+        refresh_history,
+        config.refresh_timeout_hours.map(Duration::hours),
+    )
+    .await?;
 
-        // turn history into n day average
-        let source_types_average = source_history
-            .into_iter()
-            .map(|x| (x.id, x))
-            .collect::<HashMap<_, _>>();
+    let group_ids = config
+        .include_groups
+        .as_ref()
+        .map(|x| {
+            let groups = x
+                .iter()
+                .map(|name| {
+                    let children = data_service.get_all_group_id_with_root_name(name.as_str())?;
 
-        let mut dest_types_average = dest_history
-            .into_iter()
-            .map(|x| (x.id, x))
-            .collect::<HashMap<_, _>>();
-
-        // pair
-        let pairs = source_types_average.into_iter().flat_map(|(k, v)| {
-            Some(SystemMarketsItem {
-                id: k,
-                source: v.into(),
-                destination: match dest_types_average.insert(k, Default::default()) {
-                    Some(x) => x.into(),
-                    None => {
-                        log::warn!("Destination history didn't have history for item: {}", k);
-                        return None;
+                    Ok(children)
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            Result::Ok(groups)
+        })
+        .transpose()?;
+
+    let strategy = if cli_args.is_present(cli::SELL_BUY) {
+        Strategy::SellBuy
+    } else if cli_args.is_present(cli::SELL_SELL_ZKB) {
+        Strategy::SellSellZkb
+    } else {
+        Strategy::SellSell
+    };
+    let kms = if strategy == Strategy::SellSellZkb {
+        Some(
+            cached_data::load_or_create_async(
+                cached_data::cache_path(
+                    cache_dir,
+                    "zkb_losses",
+                    format!(
+                        "{}-{}.{}",
+                        config.zkill_entity.tp.zkill_filter_string(),
+                        config.zkill_entity.id,
+                        config.sell_sell.sell_sell_zkb.zkb_download_pages
+                    ),
+                    consts::KILLMAILS_SCHEMA_VERSION,
+                    "rmp",
+                ),
+                force_refresh,
+                if force_no_refresh {
+                    None
+                } else {
+                    Some(Duration::hours(24))
+                },
+                || {
+                    let client = &esi_config.client;
+                    let config = &config;
+                    let zkb_base_url = zkb_base_url.clone();
+                    async move {
+                        let zkb =
+                            ZkbRequestsService::with_base_url(client, &config.retry, zkb_base_url);
+                        let km_service = KillmailService::new(&zkb, market);
+                        Ok(km_service
+                            .get_kill_item_frequencies(
+                                &config.zkill_entity,
+                                config.sell_sell.sell_sell_zkb.zkb_download_pages,
+                            )
+                            .await?)
                     }
                 },
-            })
-        });
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+    response_cache.save()?;
 
-        let group_ids = config
-            .include_groups
-            .as_ref()
-            .map(|x| {
-                let groups = x
-                    .iter()
-                    .map(|name| {
-                        let children =
-                            data_service.get_all_group_id_with_root_name(name.as_str())?;
-
-                        Ok(children)
-                    })
-                    .collect::<Result<Vec<_>>>()?
-                    .into_iter()
-                    .flatten()
-                    .collect::<Vec<_>>();
-                Result::Ok(groups)
+    let debug_item = cli_args
+        .value_of(cli::DEBUG_ITEM_ID)
+        .and_then(|x| x.parse::<i32>().ok());
+    let disable_filters = debug_item.is_some();
+
+    let cli_in = cli_args.value_of(cli::NAME_LENGTH);
+    let name_len = if let Some(v) = cli_in.and_then(|x| x.parse::<usize>().ok()) {
+        v
+    } else {
+        log::warn!(
+            "Value '{:?}' can't be parsed as an int. Using '{}'",
+            cli_in,
+            consts::ITEM_NAME_LEN
+        );
+        consts::ITEM_NAME_LEN.parse().unwrap()
+    };
+
+    let mut results = Vec::new();
+    for (route, (sources, destination)) in routes.iter().zip(&route_stations) {
+        let sources = route
+            .source
+            .markets()
+            .iter()
+            .zip(sources)
+            .map(|(market, station)| {
+                let fee = npc_broker_fee(character_fees.as_ref(), &data_service, station)?;
+                Ok((
+                    &market_data[&(station.station_id.id, MarketScope::Station)],
+                    config.source_hub(market, fee),
+                ))
             })
-            .transpose()?;
-
-        pairs
-            .filter_map(|it| {
-                let req_res = all_type_descriptions[&it.id].clone();
-                let req_res = match req_res {
-                    Some(x) => x,
-                    None => return None,
-                };
-
-                // include only specific groups
-                if let Some(ids) = &group_ids {
-                    if !req_res
-                        .market_group_id
-                        .map(|x| ids.contains(&x))
-                        .unwrap_or(false)
-                    {
-                        return None;
-                    }
-                }
+            .collect::<Result<Vec<_>>>()?;
+        let broker_fee_destination = match route.broker_fee_destination {
+            Some(x) => x,
+            None => npc_broker_fee(character_fees.as_ref(), &data_service, destination)?
+                .unwrap_or(config.broker_fee_destination),
+        };
+        let route_config = Config {
+            sales_tax,
+            broker_fee_destination,
+            ..config.clone()
+        };
+        let mut report = FilterReport::default();
+        let mut pairs = route_pairs(
+            &sources,
+            &market_data[&(destination.station_id.id, route.destination_scope)],
+            &all_type_descriptions,
+            group_ids.as_deref(),
+            &mut report,
+        );
+        if let Some(v) = debug_item {
+            pairs.retain(|x| x.desc.type_id == v);
+            report.rejections.retain(|x| x.type_id == v);
+        }
+        if route.destination_scope != MarketScope::Station {
+            let station_id = destination.station_id.id;
+            let list_at = pairs
+                .iter()
+                .map(|x| {
+                    x.destination
+                        .orders
+                        .iter()
+                        .least_competed_location(station_id)
+                })
+                .collect::<Vec<_>>();
+            let names =
+                data_service.station_names(&list_at.iter().copied().unique().collect_vec())?;
+            for (pair, location) in pairs.iter_mut().zip(list_at) {
+                pair.list_at = Some(match names.get(&location) {
+                    Some(name) => name.clone(),
+                    None if location == station_id => route.destination.to_string(),
+                    None => format!("structure {}", location),
+                });
+            }
+        }
+        results.push(evaluate_route(
+            route.name(),
+            strategy,
+            pairs,
+            &route_config,
+            kms.as_ref(),
+            disable_filters,
+            report,
+            name_len,
+        )?);
+    }
 
-                Some(SystemMarketsItemData {
-                    desc: req_res,
-                    source: it.source,
-                    destination: it.destination,
+    let multiple_routes = results.len() > 1;
+    let rendered = if multiple_routes {
+        let rank_by = cli_args
+            .value_of(cli::RANK_BY)
+            .and_then(RankBy::from_name)
+            .unwrap_or(RankBy::ProfitPerTrip);
+        let ranking = rank(
+            results.iter().flat_map(|x| x.ranked.clone()).collect(),
+            rank_by,
+        );
+        let data = json!({
+            "routes": results
+                .iter()
+                .map(|x| json!({ "route": x.name, "results": x.data }))
+                .collect::<Vec<_>>(),
+            "ranking": ranking,
+        });
+        let rendered = output.render(&data, &ranking, || make_table_ranking(&ranking, name_len))?;
+        if output == OutputFormat::Table {
+            results
+                .iter_mut()
+                .map(|x| {
+                    let table = TableBuilder::new()
+                        .rows(std::mem::take(&mut x.table))
+                        .build();
+                    format!("Route {}:\n{}", x.name, table.render())
                 })
-            })
-            .collect::<Vec<_>>()
+                .chain(std::iter::once(format!(
+                    "Ranking across routes:\n{}",
+                    rendered
+                )))
+                .join("\n")
+        } else {
+            rendered
+        }
+    } else {
+        let x = &mut results[0];
+        let table = std::mem::take(&mut x.table);
+        output.render(&x.data, &x.csv_rows, || table)?
     };
 
-    let mut disable_filters = false;
-    if let Some(v) = cli_args
-        .value_of(cli::DEBUG_ITEM_ID)
-        .and_then(|x| x.parse::<i32>().ok())
-    {
-        pairs.retain(|x| x.desc.type_id == v);
-        disable_filters = true;
+    if let (Some(recording), Some(path)) = (&recording, record_fixture_path) {
+        recording.save_json(path).await?;
+        log::info!("Fixture saved to {}", path);
+    }
+    if let (Some(proxy), Some(path)) = (&cassette_proxy, record_http_path) {
+        proxy.save_json(path)?;
+        log::info!("Http cassette saved to {}", path);
     }
 
-    let simple_list: Vec<_>;
-    let rows = {
-        let cli_in = cli_args.value_of(cli::NAME_LENGTH);
-        let name_len = if let Some(v) = cli_in.and_then(|x| x.parse::<usize>().ok()) {
-            v
+    println!("{}", rendered);
+
+    // machine readable output keeps stdout to itself
+    let print_extra = |text: String| {
+        if output == OutputFormat::Table {
+            println!("{}", text);
         } else {
-            log::warn!(
-                "Value '{:?}' can't be parsed as an int. Using '{}'",
-                cli_in,
-                consts::ITEM_NAME_LEN
-            );
-            consts::ITEM_NAME_LEN.parse().unwrap()
-        };
+            eprintln!("{}", text);
+        }
+    };
+    let title = |title: &str, route: &RouteResults| {
+        if multiple_routes {
+            format!("{} of route {}", title, route.name)
+        } else {
+            title.to_string()
+        }
+    };
+    match cli_args.value_of(cli::EXPLAIN) {
+        Some("json") if multiple_routes => {
+            let reports = results
+                .iter()
+                .map(|x| json!({ "route": x.name, "rejections": x.report.rejections }))
+                .collect::<Vec<_>>();
+            print_extra(serde_json::to_string_pretty(&reports)?);
+        }
+        Some("json") => print_extra(serde_json::to_string_pretty(&results[0].report)?),
+        Some(_) => {
+            for x in &results {
+                let table = TableBuilder::new()
+                    .rows(x.report.make_table(name_len))
+                    .build();
+                print_extra(format!(
+                    "{}:\n{}",
+                    title("Rejected items", x),
+                    table.render()
+                ));
+            }
+        }
+        None => (),
+    }
 
-        let sell_sell = cli_args.is_present(cli::SELL_SELL);
-        let sell_sell_zkb = cli_args.is_present(cli::SELL_SELL_ZKB);
-        let sell_buy = cli_args.is_present(cli::SELL_BUY);
-        if sell_sell || (!sell_buy && !sell_sell_zkb) {
-            log::trace!("Sell sell path.");
-            let good_items = get_good_items_sell_sell(pairs, &config, disable_filters);
-            simple_list = good_items
+    if cli_args.is_present(cli::DISPLAY_SIMPLE_LIST) {
+        for x in &results {
+            let rows = x
+                .simple_list
+                .iter()
+                .map(|it| {
+                    Row::new(vec![
+                        TableCell::new(it.name.clone()),
+                        TableCell::new(it.recommend_buy),
+                    ])
+                })
+                .collect::<Vec<_>>();
+
+            let table = TableBuilder::new()
+                .style(TableStyle::empty())
+                .separate_rows(false)
+                .has_bottom_boarder(false)
+                .has_top_boarder(false)
+                .rows(rows)
+                .build();
+            print_extra(format!("{}:\n{}", title("Item names", x), table.render()));
+        }
+    }
+    if cli_args.is_present(cli::DISPLAY_SIMPLE_LIST_PRICE) {
+        for x in &results {
+            let rows = x
+                .simple_list
+                .iter()
+                .map(|it| {
+                    Row::new(vec![
+                        TableCell::new(it.name.clone()),
+                        TableCell::new(it.recommend_buy),
+                        TableCell::new(format!("{:.2}", it.sell_price)),
+                    ])
+                })
+                .collect::<Vec<_>>();
+
+            let table = TableBuilder::new()
+                .style(TableStyle::empty())
+                .separate_rows(false)
+                .has_bottom_boarder(false)
+                .has_top_boarder(false)
+                .rows(rows)
+                .build();
+            print_extra(format!(
+                "{}:\n{}",
+                title("Item sell prices", x),
+                table.render()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    SellSell,
+    SellBuy,
+    SellSellZkb,
+}
+
+/// Results of one route. Data is kept as json so every strategy is printed the same way.
+struct RouteResults {
+    name: String,
+    data: serde_json::Value,
+    csv_rows: Vec<serde_json::Value>,
+    table: Vec<Row<'static>>,
+    ranked: Vec<RankedItem>,
+    simple_list: Vec<SimpleDisplay>,
+    report: FilterReport,
+}
+
+/// Broker fee of the character in an NPC station.
+/// `None` without character fees and for structures, their owners set the fee.
+fn npc_broker_fee(
+    fees: Option<&CharacterFees>,
+    data_service: &DatadumpService,
+    station: &StationIdData,
+) -> Result<Option<f64>> {
+    let fees = match fees {
+        Some(fees) if !station.station_id.is_citadel => fees,
+        _ => return Ok(None),
+    };
+    let fee = data_service
+        .station_owner(station.station_id.id)?
+        .map(|owner| fees.broker_fee(owner));
+    if let Some(fee) = fee {
+        log::info!(
+            "Broker fee at station {}: {:.4}",
+            station.station_id.id,
+            fee
+        );
+    }
+    Ok(fee)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn evaluate_route(
+    name: String,
+    strategy: Strategy,
+    pairs: Vec<SystemMarketsItemData>,
+    config: &Config,
+    kms: Option<&ItemFrequencies>,
+    disable_filters: bool,
+    mut report: FilterReport,
+    name_len: usize,
+) -> Result<RouteResults> {
+    let cargo_capacity = config.sell_buy.cargo_capacity as f64;
+    let (data, csv_rows, table, ranked, simple_list) = match (strategy, kms) {
+        (Strategy::SellBuy, _) => {
+            log::trace!("Sell buy path.");
+            let good_items = get_good_items_sell_buy(pairs, config, disable_filters, &mut report);
+            let ranked = good_items
+                .items
+                .iter()
+                .filter_map(|x| {
+                    RankedItem::new(
+                        &name,
+                        &x.market,
+                        x.recommend_buy,
+                        x.rough_profit,
+                        cargo_capacity,
+                    )
+                })
+                .collect();
+            let simple_list = good_items
+                .items
                 .iter()
                 .map(|x| SimpleDisplay {
                     name: x.market.desc.name.clone(),
@@ -306,12 +795,31 @@ async fn run() -> Result<()> {
                     sell_price: x.dest_min_sell_price,
                 })
                 .collect();
-            make_table_sell_sell(&good_items, name_len)
-        } else if sell_buy {
-            log::trace!("Sell buy path.");
-            let good_items = get_good_items_sell_buy(pairs, &config, disable_filters);
-            simple_list = good_items
-                .items
+            (
+                serde_json::to_value(&good_items)?,
+                to_values(&good_items.csv_rows())?,
+                make_table_sell_buy(&good_items, name_len),
+                ranked,
+                simple_list,
+            )
+        }
+        (Strategy::SellSellZkb, Some(kms)) => {
+            log::trace!("Sell sell zkb path.");
+            let good_items =
+                get_good_items_sell_sell_zkb(pairs, kms, config, disable_filters, &mut report);
+            let ranked = good_items
+                .iter()
+                .filter_map(|x| {
+                    RankedItem::new(
+                        &name,
+                        &x.market,
+                        x.recommend_buy,
+                        x.rough_profit,
+                        cargo_capacity,
+                    )
+                })
+                .collect();
+            let simple_list = good_items
                 .iter()
                 .map(|x| SimpleDisplay {
                     name: x.market.desc.name.clone(),
@@ -319,41 +827,30 @@ async fn run() -> Result<()> {
                     sell_price: x.dest_min_sell_price,
                 })
                 .collect();
-            make_table_sell_buy(&good_items, name_len)
-        } else {
-            log::trace!("Sell sell zkb path.");
-            let kms = cached_data::load_or_create_async(
-                format!(
-                    "cache/zkb_losses.{}.{}.rmp",
-                    config.zkill_entity.tp.zkill_filter_string(),
-                    config.zkill_entity.id
-                ),
-                force_refresh,
-                if force_no_refresh {
-                    None
-                } else {
-                    Some(Duration::hours(24))
-                },
-                || {
-                    let esi_requests = &esi_requests;
-                    let client = &esi_config.client;
-                    let config = &config;
-                    async move {
-                        let zkb = ZkbRequestsService::new(client);
-                        let km_service = KillmailService::new(&zkb, esi_requests);
-                        Ok(km_service
-                            .get_kill_item_frequencies(
-                                &config.zkill_entity,
-                                config.sell_sell.sell_sell_zkb.zkb_download_pages,
-                            )
-                            .await?)
-                    }
-                },
+            (
+                serde_json::to_value(&good_items)?,
+                to_values(&good_items)?,
+                make_table_sell_sell_zkb(&good_items, name_len),
+                ranked,
+                simple_list,
             )
-            .await?;
-
-            let good_items = get_good_items_sell_sell_zkb(pairs, kms, &config, disable_filters);
-            simple_list = good_items
+        }
+        _ => {
+            log::trace!("Sell sell path.");
+            let good_items = get_good_items_sell_sell(pairs, config, disable_filters, &mut report);
+            let ranked = good_items
+                .iter()
+                .filter_map(|x| {
+                    RankedItem::new(
+                        &name,
+                        &x.market,
+                        x.recommend_buy,
+                        x.rough_profit,
+                        cargo_capacity,
+                    )
+                })
+                .collect();
+            let simple_list = good_items
                 .iter()
                 .map(|x| SimpleDisplay {
                     name: x.market.desc.name.clone(),
@@ -361,53 +858,101 @@ async fn run() -> Result<()> {
                     sell_price: x.dest_min_sell_price,
                 })
                 .collect();
-            make_table_sell_sell_zkb(&good_items, name_len)
+            (
+                serde_json::to_value(&good_items)?,
+                to_values(&good_items)?,
+                make_table_sell_sell(&good_items, name_len),
+                ranked,
+                simple_list,
+            )
         }
     };
+    Ok(RouteResults {
+        name,
+        data,
+        csv_rows,
+        table,
+        ranked,
+        simple_list,
+        report,
+    })
+}
 
-    let table = TableBuilder::new().rows(rows).build();
-    println!("{}", table.render());
+fn to_values<T: Serialize>(rows: &[T]) -> Result<Vec<serde_json::Value>> {
+    Ok(rows
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<serde_json::Result<_>>()?)
+}
 
-    if cli_args.is_present(cli::DISPLAY_SIMPLE_LIST) {
-        let rows = simple_list
-            .iter()
-            .map(|it| {
-                Row::new(vec![
-                    TableCell::new(it.name.clone()),
-                    TableCell::new(it.recommend_buy),
-                ])
-            })
-            .collect::<Vec<_>>();
-
-        let table = TableBuilder::new()
-            .style(TableStyle::empty())
-            .separate_rows(false)
-            .has_bottom_boarder(false)
-            .has_top_boarder(false)
-            .rows(rows)
-            .build();
-        println!("Item names:\n{}", table.render());
-    }
-    if cli_args.is_present(cli::DISPLAY_SIMPLE_LIST_PRICE) {
-        let rows = simple_list
-            .iter()
-            .map(|it| {
-                Row::new(vec![
-                    TableCell::new(it.name.clone()),
-                    TableCell::new(it.recommend_buy),
-                    TableCell::new(format!("{:.2}", it.sell_price)),
-                ])
-            })
-            .collect::<Vec<_>>();
-
-        let table = TableBuilder::new()
-            .style(TableStyle::empty())
-            .separate_rows(false)
-            .has_bottom_boarder(false)
-            .has_top_boarder(false)
-            .rows(rows)
-            .build();
-        println!("Item sell prices:\n{}", table.render());
+fn cache_command(args: &ArgMatches) -> Result<()> {
+    match args.subcommand() {
+        Some((cli::CACHE_INSPECT, args)) => {
+            let path = args.value_of(cli::CACHE_PATH).unwrap();
+            match cached_data::inspect_entry(path) {
+                Ok(value) => println!("{}", serde_json::to_string_pretty(&value)?),
+                Err(e) => log::error!("{} can't be read: {}", path, e),
+            }
+        }
+        Some((cli::CACHE_PRUNE, args)) => {
+            // validated by clap
+            let older_than = args
+                .value_of_t::<i64>(cli::OLDER_THAN_DAYS)
+                .ok()
+                .map(Duration::days);
+            let dry_run = args.is_present(cli::DRY_RUN);
+
+            for entry in cached_data::list_entries("cache")? {
+                let too_old = matches!(
+                    (entry.time, older_than),
+                    (Some(time), Some(older_than)) if time + older_than < chrono::Utc::now()
+                );
+                if entry.broken.is_none() && !entry.is_expired() && !too_old {
+                    continue;
+                }
+                println!("Removing {}", entry.path.display());
+                if !dry_run {
+                    std::fs::remove_file(&entry.path)?;
+                }
+            }
+            if !dry_run {
+                // loading evicts responses that can't be used anymore
+                ResponseCache::load("cache/responses.rmp", false).save()?;
+            }
+        }
+        _ => {
+            let rows = cached_data::list_entries("cache")?
+                .into_iter()
+                .map(|x| {
+                    let status = match &x.broken {
+                        Some(e) => format!("broken: {}", e),
+                        None if x.is_expired() => "expired".to_string(),
+                        None => "ok".to_string(),
+                    };
+                    Row::new(vec![
+                        TableCell::new(x.path.display()),
+                        TableCell::new(format!("{} KiB", x.size / 1024)),
+                        TableCell::new(x.time.map_or(String::new(), |x| x.to_rfc3339())),
+                        TableCell::new(x.expires.map_or(String::new(), |x| x.to_rfc3339())),
+                        TableCell::new(status),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            let table = TableBuilder::new()
+                .rows(
+                    std::iter::once(Row::new(vec![
+                        TableCell::new("Path"),
+                        TableCell::new("Size"),
+                        TableCell::new("Saved"),
+                        TableCell::new("Expires"),
+                        TableCell::new("Status"),
+                    ]))
+                    .chain(rows)
+                    .collect(),
+                )
+                .build();
+            println!("{}", table.render());
+        }
     }
     Ok(())
 }
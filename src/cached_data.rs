@@ -1,8 +1,22 @@
-use std::{future::Future, path::Path};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    io::{BufRead, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
 
 use super::error::Result;
+use crate::consts::{CACHE_FORMAT_VERSION, CACHE_MAGIC};
 use chrono::{DateTime, Utc};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
 
 pub async fn load_or_create_async<T, F, FO>(
     path: impl AsRef<Path>,
@@ -15,6 +29,7 @@ where
     FO: Future<Output = Result<T>>,
     T: Serialize + DeserializeOwned,
 {
+    let gen = move || async move { gen().await.map(|x| (x, None::<DateTime<Utc>>)) };
     load_data_or_create_async(path, DataFormat::Bin, refresh, timeout, gen).await
 }
 
@@ -29,9 +44,26 @@ where
     FO: Future<Output = Result<T>>,
     T: Serialize + DeserializeOwned,
 {
+    let gen = move || async move { gen().await.map(|x| (x, None::<DateTime<Utc>>)) };
     load_data_or_create_async(path, DataFormat::Json, refresh, timeout, gen).await
 }
 
+/// Like [`load_or_create_async`], but `gen` also tells when the generated data expires.
+/// Expiration date is only used when there is no `timeout`.
+pub async fn load_or_create_expiring_async<T, F, FO>(
+    path: impl AsRef<Path>,
+    refresh: bool,
+    timeout: Option<chrono::Duration>,
+    gen: F,
+) -> Result<T>
+where
+    F: FnOnce() -> FO,
+    FO: Future<Output = Result<(T, Option<DateTime<Utc>>)>>,
+    T: Serialize + DeserializeOwned,
+{
+    load_data_or_create_async(path, DataFormat::Bin, refresh, timeout, gen).await
+}
+
 async fn load_data_or_create_async<T, F, FO>(
     path: impl AsRef<Path>,
     format: DataFormat,
@@ -41,17 +73,25 @@ async fn load_data_or_create_async<T, F, FO>(
 ) -> Result<T>
 where
     F: FnOnce() -> FO,
-    FO: Future<Output = Result<T>>,
+    FO: Future<Output = Result<(T, Option<DateTime<Utc>>)>>,
     T: Serialize + DeserializeOwned,
 {
-    let cont = if path.as_ref().exists() && !refresh {
-        let str = std::fs::read(path.as_ref()).unwrap();
-        let deser: Container<T> = match format {
-            DataFormat::Json => serde_json::from_slice(str.as_slice()).unwrap(),
-            DataFormat::Bin => rmp_serde::from_read(str.as_slice()).unwrap(),
-        };
-        match timeout {
-            Some(timeout) if deser.time + timeout < Utc::now() => {
+    let cached = if path.as_ref().exists() && !refresh {
+        read_container::<T>(path.as_ref(), &format)
+            .map_err(|e| {
+                log::warn!(
+                    "Cached file {:?} is broken and will be regenerated: {}",
+                    path.as_ref(),
+                    e
+                )
+            })
+            .ok()
+    } else {
+        None
+    };
+    let cont = if let Some(deser) = cached {
+        match (timeout, deser.expires) {
+            (Some(timeout), _) if deser.time + timeout < Utc::now() => {
                 log::debug!(
                     "Save time ({}) + timeout ({}) = {} < {}",
                     deser.time,
@@ -61,6 +101,10 @@ where
                 );
                 gen_and_save(&path, gen, format).await?
             }
+            (None, Some(expires)) if expires < Utc::now() => {
+                log::debug!("Expiration date ({}) < {}", expires, Utc::now());
+                gen_and_save(&path, gen, format).await?
+            }
             _ => {
                 log::info!("Path {:?} loaded", path.as_ref());
                 deser
@@ -79,33 +123,363 @@ async fn gen_and_save<T, F, FO>(
 ) -> Result<Container<T>>
 where
     F: FnOnce() -> FO,
-    FO: Future<Output = Result<T>>,
+    FO: Future<Output = Result<(T, Option<DateTime<Utc>>)>>,
     T: Serialize,
 {
     log::info!("Generating path {:?}", path.as_ref());
-    let generated = gen().await?;
+    let (generated, expires) = gen().await?;
     let generated = Container {
         data: generated,
         time: Utc::now(),
+        expires,
     };
-    let s = match format {
-        DataFormat::Json => serde_json::to_vec(&generated).unwrap(),
-        DataFormat::Bin => rmp_serde::to_vec(&generated).unwrap(),
+    let payload = match format {
+        DataFormat::Json => serde_json::to_vec(&generated)?,
+        DataFormat::Bin => rmp_serde::to_vec(&generated)?,
     };
-    let mut comp = path.as_ref().to_path_buf();
-    comp.pop();
-    std::fs::create_dir_all(comp).unwrap();
-    std::fs::write(path.as_ref(), s).unwrap();
+    write_atomic(path, &with_header(payload))?;
     Ok(generated)
 }
 
+/// Path of a cached file. `key` has to contain everything the data depends on
+/// and `schema_version` is the version of the layout of the cached type.
+pub fn cache_path(
+    dir: &str,
+    kind: &str,
+    key: impl Display,
+    schema_version: u32,
+    ext: &str,
+) -> String {
+    format!("{}/{}/{}.v{}.{}", dir, kind, key, schema_version, ext)
+}
+
+/// Cached files start with a line `{magic} {format version} {crc32 of the rest}`.
+fn with_header(payload: Vec<u8>) -> Vec<u8> {
+    let mut file = format!(
+        "{} {} {:08x}\n",
+        CACHE_MAGIC,
+        CACHE_FORMAT_VERSION,
+        crc32fast::hash(&payload)
+    )
+    .into_bytes();
+    file.extend(payload);
+    file
+}
+
+fn strip_header(file: &[u8]) -> std::result::Result<&[u8], String> {
+    let header_end = file.iter().position(|&x| x == b'\n').ok_or("no header")?;
+    let (header, payload) = (&file[..header_end], &file[header_end + 1..]);
+    if parse_header(header)? != crc32fast::hash(payload) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(payload)
+}
+
+/// Checksum of the payload written in the header line.
+fn parse_header(header: &[u8]) -> std::result::Result<u32, String> {
+    let header = std::str::from_utf8(header).map_err(|e| e.to_string())?;
+    let expected = format!("{} {} ", CACHE_MAGIC, CACHE_FORMAT_VERSION);
+    let checksum = header
+        .strip_prefix(&expected)
+        .ok_or_else(|| format!("unknown header \"{}\"", header))?;
+    u32::from_str_radix(checksum, 16).map_err(|e| e.to_string())
+}
+
+fn decode<T: DeserializeOwned>(
+    payload: &[u8],
+    format: &DataFormat,
+) -> std::result::Result<T, String> {
+    match format {
+        DataFormat::Json => serde_json::from_slice(payload).map_err(|e| e.to_string()),
+        DataFormat::Bin => rmp_serde::from_read(payload).map_err(|e| e.to_string()),
+    }
+}
+
+fn read_container<T: DeserializeOwned>(
+    path: &Path,
+    format: &DataFormat,
+) -> std::result::Result<Container<T>, String> {
+    let file = std::fs::read(path).map_err(|e| e.to_string())?;
+    decode(strip_header(&file)?, format)
+}
+
+/// Summary of a file in cache directory.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub time: Option<DateTime<Utc>>,
+    pub expires: Option<DateTime<Utc>>,
+    /// Why the file can't be used.
+    pub broken: Option<String>,
+}
+
+impl CacheEntry {
+    pub fn is_expired(&self) -> bool {
+        self.expires.map_or(false, |x| x < Utc::now())
+    }
+}
+
+/// All cached files under `dir`. Only files named by [`cache_path`] or starting with the cache header
+/// are entries, logs, databases and their journals are not. Neither are the files in [`NOT_ENTRIES`].
+pub fn list_entries(dir: impl AsRef<Path>) -> Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    if !dir.as_ref().exists() {
+        return Ok(entries);
+    }
+    for item in std::fs::read_dir(dir)? {
+        let path = item?.path();
+        if path.is_dir() {
+            entries.extend(list_entries(&path)?);
+            continue;
+        }
+        if path.extension().map_or(false, |x| x == "db")
+            || path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .map_or(false, |x| NOT_ENTRIES.contains(&x))
+        {
+            continue;
+        }
+
+        // files can be large, only the header line is read
+        let mut head = Vec::new();
+        std::fs::File::open(&path)?
+            .take(HEADER_MAX_LEN)
+            .read_to_end(&mut head)?;
+        if !head.starts_with(CACHE_MAGIC.as_bytes()) && !is_cache_path(&path) {
+            continue;
+        }
+        let header = head
+            .iter()
+            .position(|&x| x == b'\n')
+            .ok_or_else(|| "no header".to_string())
+            .and_then(|end| parse_header(&head[..end]));
+        let (time, expires, broken) = match header {
+            // some files like response cache are not containers, they just don't have dates
+            Ok(_) => match read_dates(&path) {
+                Ok(cont) => (Some(cont.time), cont.expires, None),
+                Err(_) => (None, None, None),
+            },
+            Err(e) => (None, None, Some(e)),
+        };
+        entries.push(CacheEntry {
+            size: std::fs::metadata(&path)?.len(),
+            path,
+            time,
+            expires,
+            broken,
+        });
+    }
+    entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Cached files that aren't market data: the login token and the path to the unpacked datadump.
+/// Removing them means logging in or downloading the datadump again, so they are never listed.
+pub const NOT_ENTRIES: [&str; 2] = ["auth", "datadump.json"];
+
+/// Header line is `{magic} {format version} {crc32}`, anything longer isn't a header.
+const HEADER_MAX_LEN: u64 = 128;
+
+/// Whether the file name is `{key}.v{schema version}.{ext}` as [`cache_path`] makes it.
+fn is_cache_path(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.rsplit_once(".v"))
+        .map_or(false, |(_, version)| version.parse::<u32>().is_ok())
+}
+
+/// Save and expiration dates of a container, the data is skipped without keeping it in memory.
+fn read_dates(path: &Path) -> std::result::Result<Container<IgnoredAny>, String> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path).map_err(|e| e.to_string())?);
+    reader
+        .read_until(b'\n', &mut Vec::new())
+        .map_err(|e| e.to_string())?;
+    match DataFormat::of(path) {
+        DataFormat::Json => serde_json::from_reader(reader).map_err(|e| e.to_string()),
+        DataFormat::Bin => rmp_serde::from_read(reader).map_err(|e| e.to_string()),
+    }
+}
+
+/// Contents of a cached file as json.
+pub fn inspect_entry(path: impl AsRef<Path>) -> std::result::Result<serde_json::Value, String> {
+    let file = std::fs::read(path.as_ref()).map_err(|e| e.to_string())?;
+    decode(strip_header(&file)?, &DataFormat::of(path.as_ref()))
+}
+
+/// Writes to a temporary file next to `path` and renames it,
+/// so an interrupted write never leaves a truncated file behind.
+pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
 enum DataFormat {
     Json,
     Bin,
 }
 
+impl DataFormat {
+    fn of(path: &Path) -> Self {
+        if path.extension().map_or(false, |x| x == "json") {
+            DataFormat::Json
+        } else {
+            DataFormat::Bin
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Container<T> {
     data: T,
     time: DateTime<Utc>,
+    #[serde(default)]
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Last successful response of every conditional request, keyed by path with parameters.
+/// Responses are reused until they expire, after that ESI can answer with 304
+/// instead of sending the same data again. Bodies of market history and orders aren't kept,
+/// the market store has them.
+pub struct ResponseCache {
+    /// `None` if the cache is disabled. Disabled cache never returns responses
+    /// and only remembers their expiration dates for the run.
+    path: Option<PathBuf>,
+    /// Fresh responses are requested again, ETags are still sent.
+    refresh: bool,
+    entries: RwLock<HashMap<String, CachedResponse>>,
+    modified: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    /// `None` if only the ETag and dates are kept.
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_modified: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub pages: Option<i32>,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self) -> bool {
+        self.expires.map_or(false, |x| x > Utc::now())
+    }
+
+    /// Expired responses without ETag can't be used for anything.
+    /// Keys of older versions started with the base url, they never match again.
+    fn is_usable(&self, key: &str) -> bool {
+        key.starts_with('/') && (self.etag.is_some() || self.is_fresh())
+    }
+}
+
+impl ResponseCache {
+    pub fn load(path: impl AsRef<Path>, refresh: bool) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut entries: HashMap<String, CachedResponse> = if path.exists() {
+            std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|x| decode(strip_header(&x)?, &DataFormat::Bin))
+                .unwrap_or_else(|e| {
+                    log::warn!("Response cache {:?} couldn't be loaded: {}", path, e);
+                    HashMap::new()
+                })
+        } else {
+            HashMap::new()
+        };
+        let count = entries.len();
+        entries.retain(|k, v| v.is_usable(k));
+        if entries.len() < count {
+            log::debug!("Evicted {} cached responses", count - entries.len());
+        }
+        Self {
+            path: Some(path),
+            refresh,
+            modified: AtomicBool::new(entries.len() < count),
+            entries: RwLock::new(entries),
+        }
+    }
+
+    /// Cache for recorded and replayed runs, saved responses would hide requests from the cassette.
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            refresh: true,
+            entries: RwLock::new(HashMap::new()),
+            modified: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        if self.path.is_none() {
+            return None;
+        }
+        self.entries.read().unwrap().get(key).cloned()
+    }
+
+    /// Previous response if it can be returned without a request.
+    pub fn get_fresh(&self, key: &str) -> Option<CachedResponse> {
+        self.get(key).filter(|x| !self.refresh && x.is_fresh())
+    }
+
+    pub fn insert(&self, key: String, mut response: CachedResponse) {
+        if self.path.is_none() {
+            response.body = None;
+        }
+        self.entries.write().unwrap().insert(key, response);
+        self.modified.store(true, Ordering::Relaxed);
+    }
+
+    /// Forgets responses whose key contains `part`.
+    pub fn evict(&self, part: &str) {
+        let mut entries = self.entries.write().unwrap();
+        let count = entries.len();
+        entries.retain(|k, _| !k.contains(part));
+        if entries.len() < count {
+            self.modified.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Earliest expiration date in the future among responses whose key starts with `prefix`.
+    pub fn earliest_expiry(&self, prefix: &str) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .filter_map(|(_, v)| v.expires)
+            .filter(|&x| x > now)
+            .min()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if !self.modified.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let s = rmp_serde::to_vec(&*self.entries.read().unwrap())?;
+        write_atomic(path, &with_header(s))?;
+        log::info!("Response cache saved to {:?}", path);
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+// reqwest manages these by itself
+const SKIPPED_REQUEST_HEADERS: &[&str] =
+    &["host", "connection", "content-length", "accept-encoding"];
+// bodies are stored decompressed
+const SKIPPED_RESPONSE_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+];
+// cassettes are meant to be shared, so tokens can't be saved
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie"];
+
+/// Every http request made during a run with its response.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub character_id: Option<i32>,
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    /// Path and query as received by the proxy. Used to match requests on replay.
+    pub path: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Cassette {
+    pub fn from_file_json<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        let cassette: Cassette = serde_json::from_str(str.as_ref())?;
+
+        Ok(cassette)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Local http server that sits between the program and remote apis.
+/// Requests to `{url}/{prefix}/...` are forwarded to the upstream registered for `prefix`
+/// and recorded, or answered from a cassette without network access.
+pub struct CassetteProxy {
+    address: String,
+    state: Arc<ProxyState>,
+}
+
+impl CassetteProxy {
+    /// `routes` is a list of (prefix, upstream base url) pairs.
+    pub fn record(client: reqwest::Client, routes: Vec<(String, String)>) -> Result<Self> {
+        Self::start(ProxyState {
+            mode: ProxyMode::Record { client, routes },
+            cassette: Mutex::new(Cassette::default()),
+        })
+    }
+
+    pub fn replay(cassette: Cassette) -> Result<Self> {
+        let mut responses: HashMap<(String, String), VecDeque<Interaction>> = HashMap::new();
+        for interaction in cassette.interactions.iter() {
+            responses
+                .entry((interaction.method.clone(), interaction.path.clone()))
+                .or_default()
+                .push_back(interaction.clone());
+        }
+        Self::start(ProxyState {
+            mode: ProxyMode::Replay {
+                responses: Mutex::new(responses),
+            },
+            cassette: Mutex::new(cassette),
+        })
+    }
+
+    fn start(state: ProxyState) -> Result<Self> {
+        let server = tiny_http::Server::http("127.0.0.1:0")
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let address = format!("http://{}", server.server_addr());
+        log::info!("Cassette proxy is listening on {}", address);
+
+        let state = Arc::new(state);
+        let runtime = tokio::runtime::Handle::current();
+        let server_state = state.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let state = server_state.clone();
+                runtime.spawn(async move { state.handle(request).await });
+            }
+        });
+
+        Ok(Self { address, state })
+    }
+
+    pub fn url(&self, prefix: &str) -> String {
+        format!("{}/{}", self.address, prefix)
+    }
+
+    pub fn character_id(&self) -> Option<i32> {
+        self.state.cassette.lock().unwrap().character_id
+    }
+
+    pub fn set_character_id(&self, character_id: i32) {
+        self.state.cassette.lock().unwrap().character_id = Some(character_id);
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.state.cassette.lock().unwrap().save_json(path)
+    }
+}
+
+enum ProxyMode {
+    Record {
+        client: reqwest::Client,
+        routes: Vec<(String, String)>,
+    },
+    Replay {
+        responses: Mutex<HashMap<(String, String), VecDeque<Interaction>>>,
+    },
+}
+
+struct ProxyState {
+    mode: ProxyMode,
+    cassette: Mutex<Cassette>,
+}
+
+impl ProxyState {
+    async fn handle(&self, mut request: tiny_http::Request) {
+        let method = request.method().to_string();
+        let path = request.url().to_string();
+        let request_headers = request
+            .headers()
+            .iter()
+            .map(|h| {
+                (
+                    h.field.as_str().as_str().to_string(),
+                    h.value.as_str().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut body = Vec::new();
+        if let Err(e) = request.as_reader().read_to_end(&mut body) {
+            log::warn!("Couldn't read body of {} {}: {}", method, path, e);
+        }
+
+        let interaction = match &self.mode {
+            ProxyMode::Record { client, routes } => {
+                let interaction = forward(client, routes, &method, &path, request_headers, body)
+                    .await
+                    .map_err(|e| log::error!("Request {} {} failed: {}", method, path, e))
+                    .ok()
+                    .flatten();
+                if let Some(interaction) = &interaction {
+                    self.cassette
+                        .lock()
+                        .unwrap()
+                        .interactions
+                        .push(interaction.clone());
+                }
+                interaction
+            }
+            ProxyMode::Replay { responses } => {
+                let mut responses = responses.lock().unwrap();
+                // the last response for a request is repeated as many times as needed
+                match responses.get_mut(&(method.clone(), path.clone())) {
+                    Some(queue) if queue.len() > 1 => queue.pop_front(),
+                    Some(queue) => queue.front().cloned(),
+                    None => {
+                        log::warn!("No recorded response for {} {}", method, path);
+                        None
+                    }
+                }
+            }
+        };
+
+        let response = match &interaction {
+            Some(interaction) => to_response(interaction),
+            None => tiny_http::Response::from_data(Vec::new()).with_status_code(match self.mode {
+                ProxyMode::Record { .. } => 502u16,
+                ProxyMode::Replay { .. } => 404,
+            }),
+        };
+        if let Err(e) = request.respond(response) {
+            log::warn!("Couldn't respond to {} {}: {}", method, path, e);
+        }
+    }
+}
+
+async fn forward(
+    client: &reqwest::Client,
+    routes: &[(String, String)],
+    method: &str,
+    path: &str,
+    request_headers: Vec<(String, String)>,
+    body: Vec<u8>,
+) -> std::result::Result<Option<Interaction>, reqwest::Error> {
+    let upstream = routes.iter().find_map(|(prefix, upstream)| {
+        path.strip_prefix(&format!("/{}", prefix))
+            .filter(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'))
+            .map(|rest| format!("{}{}", upstream, rest))
+    });
+    let url = match upstream {
+        Some(url) => url,
+        None => {
+            log::warn!("No upstream for {}", path);
+            return Ok(None);
+        }
+    };
+
+    let mut req = client
+        .request(
+            reqwest::Method::from_bytes(method.as_bytes()).unwrap_or(reqwest::Method::GET),
+            url.as_str(),
+        )
+        .body(body);
+    for (name, value) in request_headers.iter() {
+        if !SKIPPED_REQUEST_HEADERS.contains(&name.to_lowercase().as_str()) {
+            req = req.header(name.as_str(), value.as_str());
+        }
+    }
+    let response = req.send().await?;
+
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_string(),
+                value.to_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect();
+    let body = response.bytes().await?;
+
+    Ok(Some(Interaction {
+        method: method.to_string(),
+        path: path.to_string(),
+        url,
+        request_headers: request_headers
+            .into_iter()
+            .filter(|(name, _)| !SECRET_HEADERS.contains(&name.to_lowercase().as_str()))
+            .collect(),
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }))
+}
+
+fn to_response(interaction: &Interaction) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let mut response = tiny_http::Response::from_data(interaction.body.as_bytes().to_vec())
+        .with_status_code(interaction.status);
+    for (name, value) in interaction.headers.iter() {
+        if SKIPPED_RESPONSE_HEADERS.contains(&name.to_lowercase().as_str()) {
+            continue;
+        }
+        if let Ok(header) = tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            response.add_header(header);
+        }
+    }
+    response
+}
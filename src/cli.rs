@@ -11,9 +11,25 @@ pub const DISPLAY_SIMPLE_LIST_PRICE: &str = "simple-list-price";
 pub const DEBUG_ITEM_ID: &str = "debug-item";
 pub const FORCE_REFRESH: &str = "force-refresh";
 pub const FORCE_NO_REFRESH: &str = "force-no-refresh";
+pub const REFRESH_ORDERS: &str = "refresh-orders";
+pub const REFRESH_HISTORY: &str = "refresh-history";
 pub const NAME_LENGTH: &str = "name-length";
 pub const QUIET: &str = "quiet";
 pub const FILE_LOUD: &str = "file-loud";
+pub const FIXTURE: &str = "fixture";
+pub const RECORD_FIXTURE: &str = "record-fixture";
+pub const RECORD_HTTP: &str = "record-http";
+pub const REPLAY_HTTP: &str = "replay-http";
+pub const EXPLAIN: &str = "explain";
+pub const OUTPUT: &str = "output";
+pub const RANK_BY: &str = "rank-by";
+pub const CACHE: &str = "cache";
+pub const CACHE_LIST: &str = "list";
+pub const CACHE_INSPECT: &str = "inspect";
+pub const CACHE_PRUNE: &str = "prune";
+pub const CACHE_PATH: &str = "path";
+pub const OLDER_THAN_DAYS: &str = "older-than-days";
+pub const DRY_RUN: &str = "dry-run";
 
 pub fn matches() -> ArgMatches {
     let matches = Command::new("Eve Tradeworks")
@@ -58,6 +74,32 @@ pub fn matches() -> ArgMatches {
                 .default_value(ITEM_NAME_LEN),
         )
         .arg(Arg::new(DEBUG_ITEM_ID).long("debug-item").takes_value(true))
+        .arg(
+            Arg::new(OUTPUT)
+                .short('o')
+                .long("output")
+                .takes_value(true)
+                .possible_values(["table", "json", "csv"])
+                .default_value("table")
+                .help("Format of the results, logs are not printed with json and csv"),
+        )
+        .arg(
+            Arg::new(RANK_BY)
+                .long("rank-by")
+                .takes_value(true)
+                .possible_values(["trip", "m3"])
+                .default_value("trip")
+                .help("What the ranking across routes is sorted by, profit per trip or per m3"),
+        )
+        .arg(
+            Arg::new(EXPLAIN)
+                .long("explain")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("table")
+                .possible_values(["table", "json"])
+                .help("Print which filters rejected items"),
+        )
         .arg(
             Arg::new(FORCE_REFRESH)
                 .short('r')
@@ -71,8 +113,72 @@ pub fn matches() -> ArgMatches {
                 .takes_value(false)
                 .conflicts_with(FORCE_REFRESH),
         )
+        .arg(
+            Arg::new(REFRESH_ORDERS)
+                .long("refresh-orders")
+                .takes_value(false)
+                .help("Download orders even if they are fresh"),
+        )
+        .arg(
+            Arg::new(REFRESH_HISTORY)
+                .long("refresh-history")
+                .takes_value(false)
+                .help("Download history of all types, not only of the ones missing the last day"),
+        )
         .arg(Arg::new(QUIET).short('q').takes_value(false))
         .arg(Arg::new(FILE_LOUD).short('v').takes_value(false))
+        .arg(
+            Arg::new(FIXTURE)
+                .long("fixture")
+                .takes_value(true)
+                .conflicts_with(RECORD_FIXTURE),
+        )
+        .arg(
+            Arg::new(RECORD_FIXTURE)
+                .long("record-fixture")
+                .takes_value(true)
+                .conflicts_with(FIXTURE),
+        )
+        .arg(
+            Arg::new(RECORD_HTTP)
+                .long("record-http")
+                .takes_value(true)
+                .conflicts_with(REPLAY_HTTP),
+        )
+        .arg(
+            Arg::new(REPLAY_HTTP)
+                .long("replay-http")
+                .takes_value(true)
+                .conflicts_with(RECORD_HTTP),
+        )
+        .subcommand(
+            Command::new(CACHE)
+                .about("Manage cached data")
+                .subcommand_required(true)
+                .subcommand(Command::new(CACHE_LIST).about("List cached files"))
+                .subcommand(
+                    Command::new(CACHE_INSPECT)
+                        .about("Print contents of a cached file as json")
+                        .arg(Arg::new(CACHE_PATH).required(true)),
+                )
+                .subcommand(
+                    Command::new(CACHE_PRUNE)
+                        .about("Remove broken and expired cached files")
+                        .arg(
+                            Arg::new(OLDER_THAN_DAYS)
+                                .long("older-than-days")
+                                .takes_value(true)
+                                .validator(|x| x.parse::<i64>())
+                                .help("Also remove files saved earlier than this many days ago"),
+                        )
+                        .arg(
+                            Arg::new(DRY_RUN)
+                                .long("dry-run")
+                                .takes_value(false)
+                                .help("Only print what would be removed"),
+                        ),
+                ),
+        )
         .get_matches();
     matches
 }
@@ -2,7 +2,10 @@ use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{zkb::zkb_requests::ZkillEntity, Station};
+use crate::{
+    good_items::help::GapPolicy, item_type::SourceHub, requests::retry::RetryPolicy,
+    route_graph::RoutePreference, zkb::zkb_requests::ZkillEntity, MarketScope, Station,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
@@ -16,18 +19,43 @@ impl AuthConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub days_average: usize,
+    /// How days without trades are averaged.
+    #[serde(default)]
+    pub history_gaps: GapPolicy,
     pub margin_cutoff: f64,
     pub sales_tax: f64,
     pub broker_fee_source: f64,
     pub broker_fee_destination: f64,
+    /// Sales tax and broker fees of NPC stations are calculated from skills and standings of the character.
+    /// Broker fees of structures are still taken from the config.
+    #[serde(default)]
+    pub character_fees: bool,
     pub items_take: usize,
-    pub source: Station,
-    pub destination: Station,
+    /// Single route, used when `routes` is empty.
+    pub source: Option<SourceMarkets>,
+    pub destination: Option<Station>,
+    /// Destination market is the station or the whole area around it.
+    #[serde(default)]
+    pub destination_scope: MarketScope,
+    /// Routes used to check which buy orders reach a station.
+    #[serde(default)]
+    pub route_preference: RoutePreference,
+    /// Systems routes don't go through.
+    #[serde(default)]
+    pub avoid_systems: Vec<i32>,
+    /// Routes calculated in one run. Market data of a station or region is downloaded once for all of them.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
     pub zkill_entity: ZkillEntity,
-    pub refresh_timeout_hours: i64,
+    /// Fixed lifetime of downloaded orders.
+    /// If not set, orders are refreshed when ESI says they expire.
+    /// History is refreshed after every downtime.
+    pub refresh_timeout_hours: Option<i64>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
     pub min_profit: Option<f64>,
     pub include_groups: Option<Vec<String>>,
     pub sell_sell: ConfigSellSell,
@@ -38,12 +66,106 @@ impl Config {
     pub fn from_file_json<P: AsRef<Path>>(path: P) -> crate::error::Result<Self> {
         let str = std::fs::read_to_string(path)?;
         let config: Config = serde_json::from_str(str.as_ref())?;
+        if config.routes.is_empty() && (config.source.is_none() || config.destination.is_none()) {
+            return Err(crate::error::Error::Config(
+                "either `routes` or `source` and `destination` have to be set".to_string(),
+            ));
+        }
 
         Ok(config)
     }
+
+    /// `routes`, or the route between `source` and `destination`.
+    pub fn routes(&self) -> Vec<RouteConfig> {
+        match (&self.source, &self.destination) {
+            (Some(source), Some(destination)) if self.routes.is_empty() => vec![RouteConfig {
+                name: None,
+                source: source.clone(),
+                destination: destination.clone(),
+                destination_scope: self.destination_scope,
+                broker_fee_destination: None,
+            }],
+            _ => self.routes.clone(),
+        }
+    }
+
+    /// Fees and freight of the source market.
+    /// The ones it doesn't set are the character's broker fee or taken from the config.
+    pub fn source_hub(
+        &self,
+        market: &SourceMarket,
+        character_broker_fee: Option<f64>,
+    ) -> SourceHub {
+        SourceHub {
+            name: market.station.to_string(),
+            broker_fee: market
+                .broker_fee
+                .or(character_broker_fee)
+                .unwrap_or(self.broker_fee_source),
+            freight_cost_iskm3: market
+                .freight_cost_iskm3
+                .unwrap_or(self.sell_sell.freight_cost_iskm3),
+            freight_cost_collateral_percent: market
+                .freight_cost_collateral_percent
+                .unwrap_or(self.sell_sell.freight_cost_collateral_percent),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Station items are bought at.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceMarket {
+    #[serde(flatten)]
+    pub station: Station,
+    pub broker_fee: Option<f64>,
+    pub freight_cost_iskm3: Option<f64>,
+    pub freight_cost_collateral_percent: Option<f64>,
+}
+
+/// One source market or a list of them. Every item is bought where it's the most profitable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SourceMarkets {
+    One(SourceMarket),
+    Many(Vec<SourceMarket>),
+}
+
+impl SourceMarkets {
+    pub fn markets(&self) -> &[SourceMarket] {
+        match self {
+            SourceMarkets::One(x) => std::slice::from_ref(x),
+            SourceMarkets::Many(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteConfig {
+    /// Shown in the output instead of the station names.
+    pub name: Option<String>,
+    pub source: SourceMarkets,
+    pub destination: Station,
+    #[serde(default)]
+    pub destination_scope: MarketScope,
+    /// Broker fee of the destination structure, `broker_fee_destination` of the config if not set.
+    pub broker_fee_destination: Option<f64>,
+}
+
+impl RouteConfig {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            let sources = self
+                .source
+                .markets()
+                .iter()
+                .map(|x| x.station.to_string())
+                .collect::<Vec<_>>();
+            format!("{} -> {}", sources.join(" / "), self.destination)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellSell {
     pub rcmnd_fill_days: f64,
     pub min_src_volume: f64,
@@ -54,13 +176,13 @@ pub struct ConfigSellSell {
     pub sell_sell_zkb: ConfigSellSellZkb,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellSellZkb {
     pub min_dst_zkb_lost_volume: f64,
     pub zkb_download_pages: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigSellBuy {
     pub cargo_capacity: i32,
 }
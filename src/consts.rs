@@ -1,6 +1,53 @@
 pub const DATE_FMT: &str = "%Y-%m-%d";
 pub const DATE_TIME_FMT: &str = "%Y-%m-%dT%H:%M:%S%Z";
 
-pub const RETRIES: u32 = 2;
 pub const ITEM_NAME_LEN: &str = "25";
 pub const BUFFER_UNORDERED: usize = 16;
+
+pub const CACHE_MAGIC: &str = "unusable-eve-tradeworks-cache";
+// bump when layout of cached files changes
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+// bump when the cached type changes
+pub const STATION_SCHEMA_VERSION: u32 = 1;
+pub const TYPE_IDS_SCHEMA_VERSION: u32 = 1;
+pub const KILLMAILS_SCHEMA_VERSION: u32 = 1;
+// bump when tables of the market store change, stores of unknown versions are recreated
+pub const MARKET_STORE_SCHEMA_VERSION: u32 = 2;
+// ESI caches market orders for 5 minutes
+pub const ORDERS_DEFAULT_EXPIRY_MINUTES: i64 = 5;
+
+// days of history that are filled when there were no trades
+pub const HISTORY_DAYS: i64 = 360;
+// ESI publishes history of the previous day after downtime
+pub const DOWNTIME_HOUR: u32 = 11;
+
+pub const PAGES_RESTARTS: u32 = 3;
+pub const STATION_CANDIDATES: usize = 10;
+// resolved stations are looked up again after this many days
+pub const STATION_CACHE_DAYS: i64 = 30;
+
+// systems with security rounded to 0.5 and more are high sec
+pub const HIGH_SEC: f64 = 0.45;
+// jump to a system of unwanted security costs as much as this many jumps
+pub const UNWANTED_SECURITY_COST: u64 = 1000;
+
+// ESI allows 100 errors per window
+pub const ERROR_LIMIT: i32 = 100;
+pub const ERROR_LIMIT_THRESHOLD: i32 = 10;
+
+// market fees before skills and standings
+pub const SALES_TAX_BASE: f64 = 0.075;
+pub const BROKER_FEE_BASE: f64 = 0.03;
+pub const BROKER_FEE_MIN: f64 = 0.01;
+// fee reductions per skill level or standing point
+pub const ACCOUNTING_REDUCTION: f64 = 0.11;
+pub const BROKER_RELATIONS_REDUCTION: f64 = 0.003;
+pub const FACTION_STANDING_REDUCTION: f64 = 0.0003;
+pub const CORPORATION_STANDING_REDUCTION: f64 = 0.0002;
+// part of the distance to 10 standing added per level of Connections or Diplomacy
+pub const STANDING_SKILL_BONUS: f64 = 0.04;
+
+pub const ACCOUNTING_SKILL: i32 = 16622;
+pub const BROKER_RELATIONS_SKILL: i32 = 3446;
+pub const CONNECTIONS_SKILL: i32 = 3359;
+pub const DIPLOMACY_SKILL: i32 = 3357;
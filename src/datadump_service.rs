@@ -1,4 +1,11 @@
-use rusqlite::{Connection, Result};
+use std::collections::HashMap;
+
+use rusqlite::{Connection, OptionalExtension, Result};
+
+use crate::{
+    consts::STATION_CANDIDATES, error::StationResolveError, fees::StationOwner,
+    route_graph::RouteGraph, MarketScope, StationId, StationIdData,
+};
 
 pub struct DatadumpService {
     conn: Connection,
@@ -44,4 +51,146 @@ impl DatadumpService {
 
         Ok(groups)
     }
+
+    /// Resolves NPC station by its name.
+    /// Partial name is accepted if it matches only one station.
+    pub fn find_station(&self, name: &str) -> crate::error::Result<StationIdData> {
+        let exact = self
+            .conn
+            .query_row(
+                &format!("{} WHERE sta.stationName = ? COLLATE NOCASE", STATION_QUERY),
+                [name],
+                to_station,
+            )
+            .optional()?;
+        if let Some((_, station)) = exact {
+            return Ok(station);
+        }
+
+        let mut statement = self.conn.prepare(&format!(
+            "{} WHERE sta.stationName LIKE '%' || ? || '%' ORDER BY sta.stationName LIMIT ?",
+            STATION_QUERY
+        ))?;
+        let mut candidates = statement
+            .query_map(rusqlite::params![name, STATION_CANDIDATES], to_station)?
+            .collect::<Result<Vec<_>>>()?;
+        match candidates.len() {
+            0 => Err(StationResolveError::NotFound(name.to_string()).into()),
+            1 => Ok(candidates.remove(0).1),
+            _ => Err(StationResolveError::Ambiguous {
+                name: name.to_string(),
+                candidates: candidates.into_iter().map(|x| x.0).collect(),
+            }
+            .into()),
+        }
+    }
+
+    pub fn find_station_by_id(&self, station_id: i64) -> crate::error::Result<StationIdData> {
+        let station = self
+            .conn
+            .query_row(
+                &format!("{} WHERE sta.stationID = ?", STATION_QUERY),
+                [station_id],
+                to_station,
+            )
+            .optional()?;
+        match station {
+            Some((_, station)) => Ok(station),
+            None => Err(StationResolveError::NotFound(format!("station {}", station_id)).into()),
+        }
+    }
+
+    /// Systems of the system's constellation or region, only the system itself for other scopes.
+    pub fn area_systems(&self, system_id: i32, scope: MarketScope) -> Result<Vec<i32>> {
+        let column = match scope {
+            MarketScope::Constellation => "constellationID",
+            MarketScope::Region => "regionID",
+            MarketScope::Station | MarketScope::System => return Ok(vec![system_id]),
+        };
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT solarSystemID FROM mapSolarSystems
+            WHERE {0} = (SELECT {0} FROM mapSolarSystems WHERE solarSystemID = ?)",
+            column
+        ))?;
+        let systems = statement
+            .query_map([system_id], |row| row.get(0))?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(systems)
+    }
+
+    /// Names of the NPC stations among `ids`, structures aren't in the datadump.
+    pub fn station_names(&self, ids: &[i64]) -> Result<HashMap<i64, String>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT stationName FROM staStations WHERE stationID = ?")?;
+        let mut names = HashMap::new();
+        for &id in ids {
+            if let Some(name) = statement
+                .query_row([id], |row| row.get::<_, String>(0))
+                .optional()?
+            {
+                names.insert(id, name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Corporation owning the NPC station and its faction, `None` for structures.
+    pub fn station_owner(&self, station_id: i64) -> Result<Option<StationOwner>> {
+        self.conn
+            .query_row(
+                "SELECT sta.corporationID, crp.factionID
+                FROM staStations sta
+                LEFT JOIN crpNPCCorporations crp ON crp.corporationID = sta.corporationID
+                WHERE sta.stationID = ?",
+                [station_id],
+                |row| {
+                    Ok(StationOwner {
+                        corporation_id: row.get(0)?,
+                        faction_id: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// Stargate jumps of New Eden.
+    pub fn route_graph(&self) -> Result<RouteGraph> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT fromSolarSystemID, toSolarSystemID FROM mapSolarSystemJumps")?;
+        let jumps = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT solarSystemID, security FROM mapSolarSystems")?;
+        let security = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(RouteGraph::new(jumps, security))
+    }
+}
+
+const STATION_QUERY: &str = "SELECT
+                        sta.stationName, sta.stationID, sta.solarSystemID, con.regionID
+                    FROM
+                        staStations sta
+                        JOIN mapSolarSystems sys ON sys.solarSystemID = sta.solarSystemID
+                        JOIN mapConstellations con ON con.constellationID = sys.constellationID";
+
+fn to_station(row: &rusqlite::Row) -> Result<(String, StationIdData)> {
+    Ok((
+        row.get(0)?,
+        StationIdData {
+            station_id: StationId {
+                is_citadel: false,
+                id: row.get(1)?,
+            },
+            system_id: row.get(2)?,
+            region_id: row.get(3)?,
+        },
+    ))
 }
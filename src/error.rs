@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::requests::error::EsiApiError;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("market group")]
@@ -10,10 +12,45 @@ pub enum Error {
     File(#[from] std::io::Error),
     #[error("Serialization failure")]
     Serialization(#[from] serde_json::Error),
+    #[error("Serialization failure")]
+    BinSerialization(#[from] rmp_serde::encode::Error),
     #[error("Reqwest error")]
     Reqwest(#[from] reqwest::Error),
     #[error("Rusqlite error")]
     Rusqlite(#[from] rusqlite::Error),
+    #[error("Retries exhausted")]
+    RetriesExhausted(#[from] crate::requests::error::RetriesExhausted),
+    #[error("Invalid config: {0}")]
+    Config(String),
+    #[error("{0}")]
+    StationResolve(#[from] StationResolveError),
+}
+
+#[derive(Error, Debug)]
+pub enum StationResolveError {
+    #[error("Station \"{0}\" not found")]
+    NotFound(String),
+    #[error(
+        "Station name \"{name}\" is ambiguous, use the full name of one of: {}",
+        .candidates.join("; ")
+    )]
+    Ambiguous {
+        name: String,
+        candidates: Vec<String>,
+    },
+    #[error("Character has no docking access to \"{0}\"")]
+    NoDockingAccess(String),
+    #[error("Couldn't resolve station \"{name}\": {source}")]
+    Esi { name: String, source: EsiApiError },
+}
+
+impl StationResolveError {
+    pub fn esi(name: &str, source: EsiApiError) -> Self {
+        StationResolveError::Esi {
+            name: name.to_string(),
+            source,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::consts::{
+    ACCOUNTING_REDUCTION, ACCOUNTING_SKILL, BROKER_FEE_BASE, BROKER_FEE_MIN,
+    BROKER_RELATIONS_REDUCTION, BROKER_RELATIONS_SKILL, CONNECTIONS_SKILL,
+    CORPORATION_STANDING_REDUCTION, DIPLOMACY_SKILL, FACTION_STANDING_REDUCTION, SALES_TAX_BASE,
+    STANDING_SKILL_BONUS,
+};
+
+/// Skills and standings of the character that market fees depend on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CharacterFees {
+    /// Skill id -> active level.
+    pub skills: HashMap<i32, i32>,
+    /// Faction or corporation id -> standing without skills.
+    pub standings: HashMap<i32, f64>,
+}
+
+/// NPC corporation that owns a station.
+#[derive(Debug, Clone, Copy)]
+pub struct StationOwner {
+    pub corporation_id: i32,
+    pub faction_id: Option<i32>,
+}
+
+impl CharacterFees {
+    pub fn sales_tax(&self) -> f64 {
+        SALES_TAX_BASE * (1. - ACCOUNTING_REDUCTION * self.level(ACCOUNTING_SKILL) as f64)
+    }
+
+    /// Broker fee in an NPC station. Structure owners set their own broker fees.
+    pub fn broker_fee(&self, owner: StationOwner) -> f64 {
+        let faction = owner.faction_id.map_or(0., |x| self.standing(x));
+        let corporation = self.standing(owner.corporation_id);
+        (BROKER_FEE_BASE
+            - BROKER_RELATIONS_REDUCTION * self.level(BROKER_RELATIONS_SKILL) as f64
+            - FACTION_STANDING_REDUCTION * faction
+            - CORPORATION_STANDING_REDUCTION * corporation)
+            .max(BROKER_FEE_MIN)
+    }
+
+    fn level(&self, skill_id: i32) -> i32 {
+        self.skills.get(&skill_id).copied().unwrap_or(0)
+    }
+
+    /// Standing raised by Connections or Diplomacy, depending on its sign.
+    fn standing(&self, from_id: i32) -> f64 {
+        let standing = self.standings.get(&from_id).copied().unwrap_or(0.);
+        let skill = if standing < 0. {
+            DIPLOMACY_SKILL
+        } else {
+            CONNECTIONS_SKILL
+        };
+        standing + (10. - standing) * STANDING_SKILL_BONUS * self.level(skill) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{CharacterFees, StationOwner};
+    use crate::consts::{
+        ACCOUNTING_SKILL, BROKER_FEE_MIN, BROKER_RELATIONS_SKILL, CONNECTIONS_SKILL,
+        DIPLOMACY_SKILL,
+    };
+
+    const FACTION: i32 = 500001;
+    const CORPORATION: i32 = 1000035;
+    const OWNER: StationOwner = StationOwner {
+        corporation_id: CORPORATION,
+        faction_id: Some(FACTION),
+    };
+
+    fn fees(skills: &[(i32, i32)], standings: &[(i32, f64)]) -> CharacterFees {
+        CharacterFees {
+            skills: skills.iter().copied().collect::<HashMap<_, _>>(),
+            standings: standings.iter().copied().collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn sales_tax_is_reduced_by_accounting() {
+        assert_close(fees(&[], &[]).sales_tax(), 0.075);
+        assert_close(fees(&[(ACCOUNTING_SKILL, 5)], &[]).sales_tax(), 0.03375);
+    }
+
+    #[test]
+    fn broker_fee_is_reduced_by_broker_relations_and_standings() {
+        assert_close(fees(&[], &[]).broker_fee(OWNER), 0.03);
+        assert_close(
+            fees(&[(BROKER_RELATIONS_SKILL, 5)], &[]).broker_fee(OWNER),
+            0.015,
+        );
+        assert_close(
+            fees(
+                &[(BROKER_RELATIONS_SKILL, 3)],
+                &[(FACTION, 5.), (CORPORATION, 2.)],
+            )
+            .broker_fee(OWNER),
+            0.0191,
+        );
+    }
+
+    #[test]
+    fn broker_fee_does_not_go_below_floor() {
+        let fee = fees(
+            &[(BROKER_RELATIONS_SKILL, 5), (CONNECTIONS_SKILL, 5)],
+            &[(FACTION, 10.), (CORPORATION, 10.)],
+        )
+        .broker_fee(OWNER);
+        assert!(fee >= BROKER_FEE_MIN);
+        assert_close(fee, BROKER_FEE_MIN);
+    }
+
+    #[test]
+    fn connections_raise_positive_standing() {
+        let fees = fees(
+            &[(CONNECTIONS_SKILL, 5), (DIPLOMACY_SKILL, 5)],
+            &[(CORPORATION, 2.)],
+        );
+        assert_close(fees.standing(CORPORATION), 3.6);
+        // no standing counts as neutral and is raised too
+        assert_close(fees.standing(FACTION), 2.);
+    }
+
+    #[test]
+    fn diplomacy_raises_negative_standing() {
+        let fees = fees(
+            &[(CONNECTIONS_SKILL, 5), (DIPLOMACY_SKILL, 4)],
+            &[(CORPORATION, -5.)],
+        );
+        assert_close(fees.standing(CORPORATION), -2.6);
+    }
+}
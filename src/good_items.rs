@@ -1,4 +1,6 @@
 pub mod help;
+pub mod ranking;
+pub mod report;
 pub mod sell_buy;
 pub mod sell_sell;
 pub mod sell_sell_zkb;
@@ -1,13 +1,193 @@
 use itertools::Itertools;
-use ordered_float::NotNan;
+use serde::{Deserialize, Serialize, Serializer};
 
 use crate::{
     config::Config,
     item_type::{ItemHistoryDay, ItemTypeAveraged, Order, SystemMarketsItemData},
     requests::service::to_not_nan,
-    stat::AverageStat,
+    stat::{AverageStat, MedianStat},
 };
 
+use super::report::FilterReport;
+
+/// Why an item can't be evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// ESI doesn't know the type.
+    NoDescription,
+    NoSourceHistory,
+    NoDestinationHistory,
+    /// Neither sell orders nor traded volume to take the price from.
+    NoDestinationPrice,
+    NoSourceSellOrders,
+    NoProfitableBuyOrders,
+    /// Type description doesn't say how much space the item takes.
+    NoItemVolume,
+    /// Prices or costs make the profit NaN.
+    UnknownProfit,
+}
+
+impl SkipReason {
+    /// Name in the filter report.
+    pub fn filter_name(&self) -> &'static str {
+        match self {
+            SkipReason::NoDescription => "missing_description",
+            SkipReason::NoSourceHistory => "missing_source_history",
+            SkipReason::NoDestinationHistory => "missing_destination_history",
+            SkipReason::NoDestinationPrice => "missing_destination_price",
+            SkipReason::NoSourceSellOrders => "missing_source_sell_orders",
+            SkipReason::NoProfitableBuyOrders => "missing_profitable_buy_orders",
+            SkipReason::NoItemVolume => "missing_item_volume",
+            SkipReason::UnknownProfit => "unknown_profit",
+        }
+    }
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SkipReason::NoDescription => "type description is unknown",
+            SkipReason::NoSourceHistory => "no history in source",
+            SkipReason::NoDestinationHistory => "no history in destination",
+            SkipReason::NoDestinationPrice => {
+                "no sell orders and no traded volume in destination to take the price from"
+            }
+            SkipReason::NoSourceSellOrders => "no sell orders in source",
+            SkipReason::NoProfitableBuyOrders => "no buy orders in destination worth selling to",
+            SkipReason::NoItemVolume => "item volume is unknown",
+            SkipReason::UnknownProfit => "profit can't be calculated from the prices",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// What a day without trades counts as when history is averaged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapPolicy {
+    /// Day with zero volume and no price.
+    ZeroVolume,
+    /// Day is left out, volume is averaged over days with trades only.
+    Skip,
+    /// Day with zero volume and prices of the previous day with trades.
+    ForwardFill,
+    /// Day with zero volume and median prices of all days with trades.
+    #[default]
+    MedianFill,
+}
+
+/// History with gaps filled according to the policy. Skipped gaps stay `None`.
+pub fn fill_gaps(
+    policy: GapPolicy,
+    history: &[Option<ItemHistoryDay>],
+) -> Vec<Option<ItemHistoryDay>> {
+    let empty_day = |average, highest, lowest| ItemHistoryDay {
+        average,
+        highest,
+        lowest,
+        order_count: 0,
+        volume: 0,
+    };
+    match policy {
+        GapPolicy::Skip => history.to_vec(),
+        GapPolicy::ZeroVolume => history
+            .iter()
+            .map(|x| Some(x.clone().unwrap_or_else(|| empty_day(None, None, None))))
+            .collect(),
+        GapPolicy::ForwardFill => {
+            let mut previous: Option<&ItemHistoryDay> = None;
+            history
+                .iter()
+                .map(|x| match x {
+                    Some(day) => {
+                        previous = Some(day);
+                        Some(day.clone())
+                    }
+                    None => Some(match previous {
+                        Some(day) => empty_day(day.average, day.highest, day.lowest),
+                        None => empty_day(None, None, None),
+                    }),
+                })
+                .collect()
+        }
+        GapPolicy::MedianFill => {
+            let median = |price: fn(&ItemHistoryDay) -> Option<f64>| {
+                history
+                    .iter()
+                    .flatten()
+                    .filter_map(price)
+                    .map(to_not_nan)
+                    .median()
+                    .map(|x| *x)
+            };
+            let average = median(|x| x.average);
+            let highest = median(|x| x.highest);
+            let lowest = median(|x| x.lowest);
+            history
+                .iter()
+                .map(|x| {
+                    Some(
+                        x.clone()
+                            .unwrap_or_else(|| empty_day(average, highest, lowest)),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Every source hub of an item is evaluated and only the most profitable one is kept,
+/// the rest are rejected by `best_source`. Items that can't be bought anywhere are skipped
+/// with the reason of the first hub.
+pub fn best_source<T>(
+    pairs: Vec<SystemMarketsItemData>,
+    report: &mut FilterReport,
+    disable_filters: bool,
+    evaluate: impl Fn(SystemMarketsItemData) -> Result<T, SkipReason>,
+    profit: impl Fn(&T) -> f64,
+) -> Vec<T> {
+    pairs
+        .into_iter()
+        .into_group_map_by(|x| x.desc.type_id)
+        .into_values()
+        .filter_map(|pairs| {
+            let (name, type_id) = (pairs[0].desc.name.clone(), pairs[0].desc.type_id);
+            let (items, reasons): (Vec<_>, Vec<_>) = pairs
+                .into_iter()
+                .map(|x| {
+                    evaluate(x).and_then(|x| {
+                        if profit(&x).is_nan() {
+                            Err(SkipReason::UnknownProfit)
+                        } else {
+                            Ok(x)
+                        }
+                    })
+                })
+                .partition_result();
+            let mut items = items
+                .into_iter()
+                .sorted_by(|a, b| profit(b).total_cmp(&profit(a)));
+            let best = match items.next() {
+                Some(x) => x,
+                None => {
+                    report.skip(type_id, &name, reasons[0], disable_filters);
+                    return None;
+                }
+            };
+            for x in items {
+                report.reject(
+                    type_id,
+                    &name,
+                    "best_source",
+                    Some(profit(&x)),
+                    Some(profit(&best)),
+                );
+            }
+            Some(best)
+        })
+        .collect()
+}
+
 pub fn best_buy_volume_from_sell_to_sell(
     x: &[Order],
     recommend_buy_vol: i32,
@@ -21,7 +201,7 @@ pub fn best_buy_volume_from_sell_to_sell(
     for order in x
         .iter()
         .filter(|x| !x.is_buy_order)
-        .sorted_by_key(|x| NotNan::new(x.price).unwrap())
+        .sorted_by(|a, b| a.price.total_cmp(&b.price))
     {
         if max_price == 0. {
             max_price = order.price;
@@ -45,11 +225,13 @@ pub fn best_buy_volume_from_sell_to_sell(
     (max_price, recommend_bought_volume)
 }
 
-pub fn averages(config: &Config, history: &[ItemHistoryDay]) -> Option<ItemTypeAveraged> {
+pub fn averages(config: &Config, history: &[Option<ItemHistoryDay>]) -> Option<ItemTypeAveraged> {
+    let history = fill_gaps(config.history_gaps, history);
     let last_n_days = history
         .iter()
         .rev()
         .take(config.days_average)
+        .flatten()
         .collect::<Vec<_>>();
 
     let avg_price = last_n_days
@@ -58,14 +240,14 @@ pub fn averages(config: &Config, history: &[ItemHistoryDay]) -> Option<ItemTypeA
         .map(to_not_nan)
         .average()
         .map(|x| *x);
-    let avg_volume = *last_n_days
+    let avg_volume = last_n_days
         .iter()
         .map(|x| x.volume as f64)
         .map(to_not_nan)
         .average()
-        .unwrap();
+        .map(|x| *x);
     match (avg_price, avg_volume) {
-        (Some(p), v) => Some(ItemTypeAveraged {
+        (Some(p), Some(v)) => Some(ItemTypeAveraged {
             average: p,
             volume: v,
         }),
@@ -73,24 +255,33 @@ pub fn averages(config: &Config, history: &[ItemHistoryDay]) -> Option<ItemTypeA
     }
 }
 
-pub fn weighted_price(config: &Config, history: &[ItemHistoryDay]) -> f64 {
+/// Days without trades don't change the volume weighted price, so gaps are not filled.
+/// `None` if nothing with a known price was traded.
+pub fn weighted_price(config: &Config, history: &[Option<ItemHistoryDay>]) -> Option<f64> {
     let last_n_days = history
         .iter()
         .rev()
         .take(config.days_average)
+        .flatten()
+        .filter_map(|x| Some((x.average?, x.volume as f64)))
         .collect::<Vec<_>>();
 
-    let sum_volume = last_n_days.iter().map(|x| x.volume).sum::<i64>() as f64;
+    let sum_volume = last_n_days.iter().map(|x| x.1).sum::<f64>();
+    if sum_volume <= 0. {
+        return None;
+    }
 
-    last_n_days
-        .iter()
-        .map(|x| x.average.unwrap() * x.volume as f64)
-        .sum::<f64>()
-        / sum_volume
+    Some(last_n_days.iter().map(|x| x.0 * x.1).sum::<f64>() / sum_volume)
 }
 
+#[derive(Serialize)]
 pub struct PairCalculatedDataSellSellCommon {
+    #[serde(rename = "item", serialize_with = "serialize_item")]
     pub market: SystemMarketsItemData,
+    /// Source hub the item is bought at.
+    pub source: String,
+    /// Station to sell at when the destination is an area.
+    pub list_at: Option<String>,
     pub margin: f64,
     pub rough_profit: f64,
     pub market_dest_volume: i32,
@@ -105,6 +296,19 @@ pub struct PairCalculatedDataSellSellCommon {
     pub market_src_volume: i32,
 }
 
+/// First `length` characters of a name for a table column.
+pub fn shorten(name: &str, length: usize) -> String {
+    name.chars().take(length).collect()
+}
+
+/// Market data is too big for the output, only the type is written.
+pub fn serialize_item<S: Serializer>(
+    market: &SystemMarketsItemData,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    market.desc.serialize(serializer)
+}
+
 pub fn prepare_sell_sell(
     config: &Config,
     market_data: SystemMarketsItemData,
@@ -113,7 +317,8 @@ pub fn prepare_sell_sell(
     src_avgs: Option<ItemTypeAveraged>,
     dst_volume_on_market: i32,
     dst_avgs: ItemTypeAveraged,
-) -> PairCalculatedDataSellSellCommon {
+) -> Result<PairCalculatedDataSellSellCommon, SkipReason> {
+    let item_volume = market_data.desc.volume.ok_or(SkipReason::NoItemVolume)?;
     let dst_lowest_sell_order = market_data
         .destination
         .orders
@@ -123,8 +328,11 @@ pub fn prepare_sell_sell(
         .min()
         .map(|x| *x);
     let dst_weighted_price = weighted_price(config, &market_data.destination.history);
-    let dest_sell_price =
-        dst_lowest_sell_order.map_or(dst_weighted_price, |x| x.min(dst_weighted_price));
+    let dest_sell_price = dst_lowest_sell_order
+        .into_iter()
+        .chain(dst_weighted_price)
+        .reduce(f64::min)
+        .ok_or(SkipReason::NoDestinationPrice)?;
     let max_buy_vol = (volume_dest * config.sell_sell.rcmnd_fill_days)
         .max(1.)
         .min(src_volume_on_market as f64)
@@ -133,21 +341,24 @@ pub fn prepare_sell_sell(
         market_data.source.orders.as_slice(),
         max_buy_vol,
         dest_sell_price,
-        config.broker_fee_source,
+        market_data.source_hub.broker_fee,
         config.broker_fee_destination,
         config.sales_tax,
     );
-    let buy_price = buy_from_src_price * (1. + config.broker_fee_source);
+    let hub = &market_data.source_hub;
+    let buy_price = buy_from_src_price * (1. + hub.broker_fee);
     let expenses = buy_price
-        + market_data.desc.volume.unwrap() as f64 * config.sell_sell.freight_cost_iskm3
-        + buy_price * config.sell_sell.freight_cost_collateral_percent;
+        + item_volume as f64 * hub.freight_cost_iskm3
+        + buy_price * hub.freight_cost_collateral_percent;
     let sell_price_with_taxes =
         dest_sell_price * (1. - config.broker_fee_destination - config.sales_tax);
     let margin = (sell_price_with_taxes - expenses) / expenses;
     let rough_profit = (sell_price_with_taxes - expenses) * buy_from_src_volume as f64;
     let filled_for_days =
         (volume_dest > 0.).then(|| 1. / volume_dest * dst_volume_on_market as f64);
-    PairCalculatedDataSellSellCommon {
+    Ok(PairCalculatedDataSellSellCommon {
+        source: market_data.source_hub.name.clone(),
+        list_at: market_data.list_at.clone(),
         market: market_data,
         margin,
         rough_profit,
@@ -161,5 +372,121 @@ pub fn prepare_sell_sell(
         market_src_volume: src_volume_on_market,
         src_avgs,
         dst_avgs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item_type::ItemHistoryDay;
+
+    use super::{fill_gaps, shorten, GapPolicy};
+
+    fn day(price: f64, volume: i64) -> Option<ItemHistoryDay> {
+        Some(ItemHistoryDay {
+            average: Some(price),
+            highest: Some(price),
+            lowest: Some(price),
+            order_count: 1,
+            volume,
+        })
+    }
+
+    /// Leading, interior and trailing gap.
+    fn history() -> Vec<Option<ItemHistoryDay>> {
+        vec![
+            None,
+            day(10., 100),
+            None,
+            day(30., 300),
+            day(20., 200),
+            None,
+        ]
+    }
+
+    /// Average price and volume of every day.
+    fn filled(policy: GapPolicy) -> Vec<Option<(Option<f64>, i64)>> {
+        fill_gaps(policy, &history())
+            .into_iter()
+            .map(|x| x.map(|x| (x.average, x.volume)))
+            .collect()
+    }
+
+    #[test]
+    fn skip_leaves_gaps() {
+        assert_eq!(
+            filled(GapPolicy::Skip),
+            vec![
+                None,
+                Some((Some(10.), 100)),
+                None,
+                Some((Some(30.), 300)),
+                Some((Some(20.), 200)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_volume_fills_gaps_without_price() {
+        assert_eq!(
+            filled(GapPolicy::ZeroVolume),
+            vec![
+                Some((None, 0)),
+                Some((Some(10.), 100)),
+                Some((None, 0)),
+                Some((Some(30.), 300)),
+                Some((Some(20.), 200)),
+                Some((None, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_fill_takes_price_of_previous_trades() {
+        assert_eq!(
+            filled(GapPolicy::ForwardFill),
+            vec![
+                // nothing to take the price from before the first trade
+                Some((None, 0)),
+                Some((Some(10.), 100)),
+                Some((Some(10.), 0)),
+                Some((Some(30.), 300)),
+                Some((Some(20.), 200)),
+                Some((Some(20.), 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn median_fill_takes_median_price_of_all_trades() {
+        assert_eq!(
+            filled(GapPolicy::MedianFill),
+            vec![
+                Some((Some(20.), 0)),
+                Some((Some(10.), 100)),
+                Some((Some(20.), 0)),
+                Some((Some(30.), 300)),
+                Some((Some(20.), 200)),
+                Some((Some(20.), 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn median_fill_of_history_without_trades_has_no_price() {
+        assert_eq!(
+            fill_gaps(GapPolicy::MedianFill, &[None, None])
+                .into_iter()
+                .map(|x| x.map(|x| (x.average, x.volume)))
+                .collect::<Vec<_>>(),
+            vec![Some((None, 0)), Some((None, 0))]
+        );
+    }
+
+    #[test]
+    fn shorten_cuts_multibyte_names_by_characters() {
+        assert_eq!(shorten("Jita IV - Moon 4", 4), "Jita");
+        assert_eq!(shorten("Ямаль ✓ хаб", 7), "Ямаль ✓");
+        assert_eq!(shorten("Амарр", 50), "Амарр");
     }
 }
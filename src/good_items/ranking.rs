@@ -0,0 +1,110 @@
+use serde::Serialize;
+use term_table::{row::Row, table_cell::TableCell};
+
+use crate::item_type::SystemMarketsItemData;
+
+use super::help::shorten;
+
+/// What the ranking across routes is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankBy {
+    ProfitPerTrip,
+    ProfitPerM3,
+}
+
+impl RankBy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "trip" => Some(RankBy::ProfitPerTrip),
+            "m3" => Some(RankBy::ProfitPerM3),
+            _ => None,
+        }
+    }
+}
+
+/// Result of one route in the ranking across routes.
+#[derive(Debug, Clone, Serialize)]
+pub struct RankedItem {
+    pub route: String,
+    /// Source hub the item is bought at.
+    pub source: String,
+    pub type_id: i32,
+    pub name: String,
+    pub recommend_buy: i32,
+    pub rough_profit: f64,
+    /// Volume of all recommended items.
+    pub volume_m3: f64,
+    pub profit_per_m3: f64,
+    /// Profit of the recommended items that fit into one cargo hold.
+    pub profit_per_trip: f64,
+}
+
+impl RankedItem {
+    pub fn new(
+        route: &str,
+        market: &SystemMarketsItemData,
+        recommend_buy: i32,
+        rough_profit: f64,
+        cargo_capacity: f64,
+    ) -> Option<Self> {
+        let desc = &market.desc;
+        let item_volume = desc.volume? as f64;
+        if recommend_buy <= 0 || item_volume <= 0. {
+            return None;
+        }
+        let volume_m3 = item_volume * recommend_buy as f64;
+        let per_trip = ((cargo_capacity / item_volume).floor() as i32).min(recommend_buy);
+        Some(RankedItem {
+            route: route.to_string(),
+            source: market.source_hub.name.clone(),
+            type_id: desc.type_id,
+            name: desc.name.clone(),
+            recommend_buy,
+            rough_profit,
+            volume_m3,
+            profit_per_m3: rough_profit / volume_m3,
+            profit_per_trip: rough_profit / recommend_buy as f64 * per_trip as f64,
+        })
+    }
+}
+
+pub fn rank(mut items: Vec<RankedItem>, by: RankBy) -> Vec<RankedItem> {
+    let key = |x: &RankedItem| match by {
+        RankBy::ProfitPerTrip => (x.profit_per_trip, x.profit_per_m3),
+        RankBy::ProfitPerM3 => (x.profit_per_m3, x.profit_per_trip),
+    };
+    items.sort_unstable_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+        b.0.total_cmp(&a.0).then(b.1.total_cmp(&a.1))
+    });
+    items
+}
+
+pub fn make_table_ranking<'b>(items: &[RankedItem], name_length: usize) -> Vec<Row<'b>> {
+    std::iter::once(Row::new(vec![
+        TableCell::new("route"),
+        TableCell::new("id"),
+        TableCell::new("item name"),
+        TableCell::new("src hub"),
+        TableCell::new("rcmnd vlm"),
+        TableCell::new("rough prft"),
+        TableCell::new("m3"),
+        TableCell::new("prft/m3"),
+        TableCell::new("prft/trip"),
+    ]))
+    .chain(items.iter().map(|it| {
+        let short_name = shorten(&it.name, name_length);
+        Row::new(vec![
+            TableCell::new(it.route.clone()),
+            TableCell::new(format!("{}", it.type_id)),
+            TableCell::new(short_name),
+            TableCell::new(shorten(&it.source, name_length)),
+            TableCell::new(format!("{}", it.recommend_buy)),
+            TableCell::new(format!("{:.2}", it.rough_profit)),
+            TableCell::new(format!("{:.2}", it.volume_m3)),
+            TableCell::new(format!("{:.2}", it.profit_per_m3)),
+            TableCell::new(format!("{:.2}", it.profit_per_trip)),
+        ])
+    }))
+    .collect()
+}
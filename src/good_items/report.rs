@@ -0,0 +1,133 @@
+use serde::Serialize;
+use term_table::{row::Row, table_cell::TableCell};
+
+use super::help::{shorten, SkipReason};
+
+/// Every reason candidates didn't make it into the results, with the values that were compared.
+#[derive(Debug, Default, Serialize)]
+pub struct FilterReport {
+    pub rejections: Vec<Rejection>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    pub type_id: i32,
+    pub name: String,
+    /// Config option or missing data that rejected the item.
+    pub filter: &'static str,
+    pub value: Option<f64>,
+    pub limit: Option<f64>,
+}
+
+/// Filters of one item. Every failed filter is recorded, not only the first one.
+pub struct Checks<'a> {
+    report: &'a mut FilterReport,
+    type_id: i32,
+    name: &'a str,
+    passed: bool,
+}
+
+impl FilterReport {
+    pub fn reject(
+        &mut self,
+        type_id: i32,
+        name: &str,
+        filter: &'static str,
+        value: Option<f64>,
+        limit: Option<f64>,
+    ) {
+        self.rejections.push(Rejection {
+            type_id,
+            name: name.to_string(),
+            filter,
+            value,
+            limit,
+        });
+    }
+
+    /// Skipped items are only logged to the file unless they are debugged with `--debug-item`.
+    pub fn skip(&mut self, type_id: i32, name: &str, reason: SkipReason, debug: bool) {
+        if debug {
+            log::warn!("Item {} ({}) is skipped: {}.", name, type_id, reason);
+        } else {
+            log::debug!("Item {} ({}) is skipped: {}.", name, type_id, reason);
+        }
+        self.reject(type_id, name, reason.filter_name(), None, None);
+    }
+
+    pub fn checks<'a>(&'a mut self, type_id: i32, name: &'a str) -> Checks<'a> {
+        Checks {
+            report: self,
+            type_id,
+            name,
+            passed: true,
+        }
+    }
+
+    /// Items that are past `take` in the sorted `items` are rejected by `filter`.
+    pub fn take<T>(
+        &mut self,
+        mut items: Vec<T>,
+        take: usize,
+        filter: &'static str,
+        id: impl Fn(&T) -> (i32, &str),
+    ) -> Vec<T> {
+        for (i, item) in items.iter().enumerate().skip(take) {
+            let (type_id, name) = id(item);
+            self.reject(
+                type_id,
+                name,
+                filter,
+                Some(i as f64 + 1.),
+                Some(take as f64),
+            );
+        }
+        items.truncate(take);
+        items
+    }
+
+    pub fn make_table<'b>(&self, name_length: usize) -> Vec<Row<'b>> {
+        std::iter::once(Row::new(vec![
+            TableCell::new("id"),
+            TableCell::new("item name"),
+            TableCell::new("filter"),
+            TableCell::new("value"),
+            TableCell::new("limit"),
+        ]))
+        .chain(self.rejections.iter().map(|it| {
+            let short_name = shorten(&it.name, name_length);
+            Row::new(vec![
+                TableCell::new(format!("{}", it.type_id)),
+                TableCell::new(short_name),
+                TableCell::new(it.filter),
+                TableCell::new(it.value.map_or(String::new(), |x| format!("{:.2}", x))),
+                TableCell::new(it.limit.map_or(String::new(), |x| format!("{:.2}", x))),
+            ])
+        }))
+        .collect()
+    }
+}
+
+impl<'a> Checks<'a> {
+    /// Passes if `value` is greater than `limit`.
+    pub fn above(&mut self, filter: &'static str, value: f64, limit: f64) {
+        self.check(filter, value, limit, value > limit);
+    }
+
+    /// Passes if `value` is less than `limit`.
+    pub fn below(&mut self, filter: &'static str, value: f64, limit: f64) {
+        self.check(filter, value, limit, value < limit);
+    }
+
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+
+    fn check(&mut self, filter: &'static str, value: f64, limit: f64, passed: bool) {
+        if !passed {
+            self.report
+                .reject(self.type_id, self.name, filter, Some(value), Some(limit));
+            self.passed = false;
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use good_lp::SolverModel;
 use itertools::Itertools;
-use ordered_float::NotNan;
+use serde::Serialize;
 use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
@@ -9,141 +9,174 @@ use crate::{
     order_ext::OrderIterExt,
 };
 
-use super::help::averages;
+use super::{
+    help::{averages, best_source, serialize_item, shorten, SkipReason},
+    report::FilterReport,
+};
+
 pub fn get_good_items_sell_buy(
     pairs: Vec<SystemMarketsItemData>,
     config: &Config,
     disable_filters: bool,
+    report: &mut FilterReport,
 ) -> ProcessedSellBuyItems {
-    pairs
+    let items = best_source(
+        pairs,
+        report,
+        disable_filters,
+        |x| evaluate(config, x),
+        |x| x.best_rough_profit,
+    );
+    let items = items
         .into_iter()
-        .filter_map(|x| {
-            let src_mkt_orders = x.source.orders.clone();
-            let src_mkt_volume = src_mkt_orders.iter().sell_order_volume();
-
-            let dst_mkt_orders = x.destination.orders.clone();
-            let dst_mkt_volume: i32 = dst_mkt_orders.iter().sell_order_volume();
-
-            let src_avgs = averages(config, &x.source.history);
-            let dst_avgs = averages(config, &x.destination.history);
-
-            let (recommend_buy_vol, dest_sell_price, max_buy_price, avg_buy_price) = {
-                let mut source_sell_orders = x
-                    .source
-                    .orders
-                    .iter()
-                    .cloned()
-                    .filter(|x| !x.is_buy_order)
-                    .sorted_by_key(|x| NotNan::new(x.price).unwrap());
-
-                let mut curr_src_sell_order = source_sell_orders.next()?;
-
-                let mut recommend_bought_volume = 0;
-                let mut sum_sell_price = 0.;
-                let mut max_buy_price = 0.;
-                let mut sum_buy_price = 0.;
-                'outer: for buy_order in x
-                    .destination
-                    .orders
-                    .iter()
-                    .filter(|x| x.is_buy_order)
-                    .sorted_by_key(|x| NotNan::new(-x.price).unwrap())
-                {
-                    let mut buy_order_fulfilled = buy_order.volume_remain;
-                    while buy_order_fulfilled > 0 {
-                        let bought_volume =
-                            buy_order_fulfilled.min(curr_src_sell_order.volume_remain);
-                        buy_order_fulfilled -= bought_volume;
-
-                        let expenses = (curr_src_sell_order.price
-                            * (1. + config.broker_fee_source))
-                            * bought_volume as f64;
-
-                        let sell_price =
-                            bought_volume as f64 * buy_order.price * (1. - config.sales_tax);
-
-                        if expenses >= sell_price {
-                            break;
-                        }
-                        sum_buy_price += curr_src_sell_order.price * bought_volume as f64;
-                        curr_src_sell_order.volume_remain -= bought_volume;
-                        max_buy_price = curr_src_sell_order.price.max(max_buy_price);
-                        sum_sell_price += buy_order.price * bought_volume as f64;
-                        recommend_bought_volume += bought_volume;
-
-                        if curr_src_sell_order.volume_remain == 0 {
-                            curr_src_sell_order = if let Some(x) = source_sell_orders.next() {
-                                x
-                            } else {
-                                break 'outer;
-                            }
-                        }
+        .filter(|x| {
+            let mut checks = report.checks(x.market.desc.type_id, &x.market.desc.name);
+            checks.above("margin_cutoff", x.best_margin, config.margin_cutoff);
+            if let Some(min_profit) = config.min_profit {
+                checks.above("min_profit", x.best_rough_profit, min_profit);
+            }
+            disable_filters || checks.passed()
+        })
+        .sorted_unstable_by(|a, b| b.best_rough_profit.total_cmp(&a.best_rough_profit))
+        .collect::<Vec<_>>();
+    items.take_maximizing_profit(config.sell_buy.cargo_capacity, report)
+}
+
+fn evaluate(
+    config: &Config,
+    x: SystemMarketsItemData,
+) -> Result<PairCalculatedDataSellBuy, SkipReason> {
+    if x.desc.volume.is_none() {
+        return Err(SkipReason::NoItemVolume);
+    }
+    let broker_fee = x.source_hub.broker_fee;
+
+    let src_mkt_orders = x.source.orders.clone();
+    let src_mkt_volume = src_mkt_orders.iter().sell_order_volume();
+
+    let dst_mkt_orders = x.destination.orders.clone();
+    let dst_mkt_volume: i32 = dst_mkt_orders.iter().sell_order_volume();
+
+    let src_avgs = averages(config, &x.source.history);
+    let dst_avgs = averages(config, &x.destination.history);
+
+    let (recommend_buy_vol, dest_sell_price, max_buy_price, avg_buy_price) = {
+        let mut source_sell_orders = x
+            .source
+            .orders
+            .iter()
+            .cloned()
+            .filter(|x| !x.is_buy_order)
+            .sorted_by(|a, b| a.price.total_cmp(&b.price));
+
+        let mut curr_src_sell_order = source_sell_orders
+            .next()
+            .ok_or(SkipReason::NoSourceSellOrders)?;
+
+        let mut recommend_bought_volume = 0;
+        let mut sum_sell_price = 0.;
+        let mut max_buy_price = 0.;
+        let mut sum_buy_price = 0.;
+        'outer: for buy_order in x
+            .destination
+            .orders
+            .iter()
+            .filter(|x| x.is_buy_order)
+            .sorted_by(|a, b| b.price.total_cmp(&a.price))
+        {
+            let mut buy_order_fulfilled = buy_order.volume_remain;
+            while buy_order_fulfilled > 0 {
+                let bought_volume = buy_order_fulfilled.min(curr_src_sell_order.volume_remain);
+                buy_order_fulfilled -= bought_volume;
+
+                let expenses =
+                    (curr_src_sell_order.price * (1. + broker_fee)) * bought_volume as f64;
+
+                let sell_price = bought_volume as f64 * buy_order.price * (1. - config.sales_tax);
+
+                if expenses >= sell_price {
+                    break;
+                }
+                sum_buy_price += curr_src_sell_order.price * bought_volume as f64;
+                curr_src_sell_order.volume_remain -= bought_volume;
+                max_buy_price = curr_src_sell_order.price.max(max_buy_price);
+                sum_sell_price += buy_order.price * bought_volume as f64;
+                recommend_bought_volume += bought_volume;
+
+                if curr_src_sell_order.volume_remain == 0 {
+                    curr_src_sell_order = if let Some(x) = source_sell_orders.next() {
+                        x
+                    } else {
+                        break 'outer;
                     }
                 }
+            }
+        }
 
-                (
-                    recommend_bought_volume,
-                    sum_sell_price / recommend_bought_volume as f64,
-                    max_buy_price,
-                    sum_buy_price / recommend_bought_volume as f64,
-                )
-            };
-
-            // multibuy can only buy at a fixed price, so all buys from multiple sell orders
-            // with different prices have you paid the same price for all of them
-            let expenses = max_buy_price;
-            let buy_with_broker_fee = expenses * (1. + config.broker_fee_source);
-            let fin_sell_price = dest_sell_price * (1. - config.sales_tax);
-
-            let margin = (fin_sell_price - buy_with_broker_fee) / buy_with_broker_fee;
-
-            let rough_profit = (fin_sell_price - buy_with_broker_fee) * recommend_buy_vol as f64;
-
-            // also calculate avg buy price
-            let best_expenses = avg_buy_price;
-            let buy_with_broker_fee = best_expenses * (1. + config.broker_fee_source);
-            let fin_sell_price = dest_sell_price * (1. - config.sales_tax);
-
-            let best_margin = (fin_sell_price - buy_with_broker_fee) / buy_with_broker_fee;
-
-            let best_rough_profit =
-                (fin_sell_price - buy_with_broker_fee) * recommend_buy_vol as f64;
-
-            Some(PairCalculatedDataSellBuy {
-                market: x,
-                margin,
-                best_margin,
-                rough_profit,
-                best_rough_profit,
-                market_dest_volume: dst_mkt_volume,
-                recommend_buy: recommend_buy_vol,
-                expenses: buy_with_broker_fee,
-                sell_price: fin_sell_price,
-                src_buy_price: expenses,
-                dest_min_sell_price: dest_sell_price,
-                market_src_volume: src_mkt_volume,
-                src_avgs,
-                dst_avgs,
-            })
-        })
-        .filter(|x| disable_filters || x.best_margin > config.margin_cutoff)
-        .filter(|x| {
-            disable_filters
-                || config
-                    .min_profit
-                    .map_or(true, |min_prft| x.best_rough_profit > min_prft)
-        })
-        .sorted_unstable_by_key(|x| NotNan::new(-x.best_rough_profit).unwrap())
-        .collect::<Vec<_>>()
-        .take_maximizing_profit(config.sell_buy.cargo_capacity)
+        if recommend_bought_volume == 0 {
+            return Err(SkipReason::NoProfitableBuyOrders);
+        }
+
+        (
+            recommend_bought_volume,
+            sum_sell_price / recommend_bought_volume as f64,
+            max_buy_price,
+            sum_buy_price / recommend_bought_volume as f64,
+        )
+    };
+
+    // multibuy can only buy at a fixed price, so all buys from multiple sell orders
+    // with different prices have you paid the same price for all of them
+    let expenses = max_buy_price;
+    let buy_with_broker_fee = expenses * (1. + broker_fee);
+    let fin_sell_price = dest_sell_price * (1. - config.sales_tax);
+
+    let margin = (fin_sell_price - buy_with_broker_fee) / buy_with_broker_fee;
+
+    let rough_profit = (fin_sell_price - buy_with_broker_fee) * recommend_buy_vol as f64;
+
+    // also calculate avg buy price
+    let best_expenses = avg_buy_price;
+    let buy_with_broker_fee = best_expenses * (1. + broker_fee);
+    let fin_sell_price = dest_sell_price * (1. - config.sales_tax);
+
+    let best_margin = (fin_sell_price - buy_with_broker_fee) / buy_with_broker_fee;
+
+    let best_rough_profit = (fin_sell_price - buy_with_broker_fee) * recommend_buy_vol as f64;
+
+    Ok(PairCalculatedDataSellBuy {
+        source: x.source_hub.name.clone(),
+        market: x,
+        margin,
+        best_margin,
+        rough_profit,
+        best_rough_profit,
+        market_dest_volume: dst_mkt_volume,
+        recommend_buy: recommend_buy_vol,
+        expenses: buy_with_broker_fee,
+        sell_price: fin_sell_price,
+        src_buy_price: expenses,
+        dest_min_sell_price: dest_sell_price,
+        market_src_volume: src_mkt_volume,
+        src_avgs,
+        dst_avgs,
+    })
 }
 
 trait DataVecExt {
-    fn take_maximizing_profit(self, max_cargo: i32) -> ProcessedSellBuyItems;
+    fn take_maximizing_profit(
+        self,
+        max_cargo: i32,
+        report: &mut FilterReport,
+    ) -> ProcessedSellBuyItems;
 }
 
 impl DataVecExt for Vec<PairCalculatedDataSellBuy> {
-    fn take_maximizing_profit(self, max_cargo: i32) -> ProcessedSellBuyItems {
+    fn take_maximizing_profit(
+        self,
+        max_cargo: i32,
+        report: &mut FilterReport,
+    ) -> ProcessedSellBuyItems {
         use good_lp::{default_solver, variable, Expression, ProblemVariables, Solution, Variable};
         let mut vars = ProblemVariables::new();
         let mut var_refs = Vec::new();
@@ -187,7 +220,19 @@ impl DataVecExt for Vec<PairCalculatedDataSellBuy> {
                 item
             },
         )
-        .filter(|x: &PairCalculatedDataSellBuy| x.recommend_buy > 0)
+        .filter(|x: &PairCalculatedDataSellBuy| {
+            // cargo is better spent on other items
+            if x.recommend_buy <= 0 {
+                report.reject(
+                    x.market.desc.type_id,
+                    &x.market.desc.name,
+                    "cargo_capacity",
+                    None,
+                    Some(max_cargo as f64),
+                );
+            }
+            x.recommend_buy > 0
+        })
         .collect::<Vec<_>>();
         ProcessedSellBuyItems {
             items: recommended_items,
@@ -204,6 +249,7 @@ pub fn make_table_sell_buy<'a, 'b>(
     let rows = std::iter::once(Row::new(vec![
         TableCell::new("id"),
         TableCell::new("item name"),
+        TableCell::new("src hub"),
         TableCell::new("src prc"),
         TableCell::new("dst prc"),
         TableCell::new("expenses"),
@@ -218,11 +264,11 @@ pub fn make_table_sell_buy<'a, 'b>(
         TableCell::new("rcmnd vlm"),
     ]))
     .chain(good_items.items.iter().map(|it| {
-        let short_name =
-            it.market.desc.name[..(name_length.min(it.market.desc.name.len()))].to_owned();
+        let short_name = shorten(&it.market.desc.name, name_length);
         Row::new(vec![
             TableCell::new(format!("{}", it.market.desc.type_id)),
             TableCell::new(short_name),
+            TableCell::new(shorten(&it.source, name_length)),
             TableCell::new(format!("{:.2}", it.src_buy_price)),
             TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
             TableCell::new(format!("{:.2}", it.expenses)),
@@ -251,19 +297,22 @@ pub fn make_table_sell_buy<'a, 'b>(
     }))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total profit"),
-        TableCell::new_with_col_span(format!("{}", good_items.sum_profit), 13),
+        TableCell::new_with_col_span(format!("{}", good_items.sum_profit), 14),
     ])))
     .chain(std::iter::once(Row::new(vec![
         TableCell::new("total volume"),
-        TableCell::new_with_col_span(format!("{}", good_items.sum_volume), 13),
+        TableCell::new_with_col_span(format!("{}", good_items.sum_volume), 14),
     ])))
     .collect::<Vec<_>>();
     rows
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PairCalculatedDataSellBuy {
+    #[serde(rename = "item", serialize_with = "serialize_item")]
     pub market: SystemMarketsItemData,
+    /// Source hub the item is bought at.
+    pub source: String,
     pub margin: f64,
     pub rough_profit: f64,
     pub market_dest_volume: i32,
@@ -278,8 +327,32 @@ pub struct PairCalculatedDataSellBuy {
     best_rough_profit: f64,
     best_margin: f64,
 }
+
+#[derive(Serialize)]
 pub struct ProcessedSellBuyItems {
     pub items: Vec<PairCalculatedDataSellBuy>,
     pub sum_profit: f64,
     pub sum_volume: i32,
 }
+
+/// Csv has no place for totals, so every row repeats them.
+#[derive(Serialize)]
+pub struct SellBuyCsvRow<'a> {
+    #[serde(flatten)]
+    pub item: &'a PairCalculatedDataSellBuy,
+    pub sum_profit: f64,
+    pub sum_volume: i32,
+}
+
+impl ProcessedSellBuyItems {
+    pub fn csv_rows(&self) -> Vec<SellBuyCsvRow<'_>> {
+        self.items
+            .iter()
+            .map(|item| SellBuyCsvRow {
+                item,
+                sum_profit: self.sum_profit,
+                sum_volume: self.sum_volume,
+            })
+            .collect()
+    }
+}
@@ -1,5 +1,5 @@
 use itertools::Itertools;
-use ordered_float::NotNan;
+use serde::Serialize;
 use term_table::{row::Row, table_cell::TableCell};
 
 use crate::{
@@ -7,78 +7,105 @@ use crate::{
     zkb::killmails::ItemFrequencies,
 };
 
-use super::help::{averages, prepare_sell_sell, PairCalculatedDataSellSellCommon};
+use super::{
+    help::{
+        averages, best_source, prepare_sell_sell, shorten, PairCalculatedDataSellSellCommon,
+        SkipReason,
+    },
+    report::FilterReport,
+};
 
 pub fn get_good_items_sell_sell_zkb(
     pairs: Vec<SystemMarketsItemData>,
-    zkb_items: ItemFrequencies,
+    zkb_items: &ItemFrequencies,
     config: &Config,
     disable_filters: bool,
+    report: &mut FilterReport,
 ) -> Vec<PairCalculatedDataSellSellZkb> {
-    pairs
+    let items = best_source(
+        pairs,
+        report,
+        disable_filters,
+        |x| evaluate(config, zkb_items, x),
+        |x| x.rough_profit,
+    );
+    let items = items
         .into_iter()
-        .filter_map(|x| -> Option<_> {
-            let item_lose_popularity = *zkb_items.items.get(&x.desc.type_id).unwrap_or(&0);
-            let period_days = (zkb_items.period_seconds as f64) / 60. / 60. / 24.;
-            let lost_per_day = item_lose_popularity as f64 / period_days;
+        .filter(|x| {
+            let mut checks = report.checks(x.market.desc.type_id, &x.market.desc.name);
+            checks.above("margin_cutoff", x.margin, config.margin_cutoff);
+            checks.above(
+                "min_src_volume",
+                x.src_avgs.map(|x| x.volume).unwrap_or(0f64),
+                config.sell_sell.min_src_volume,
+            );
+            checks.above(
+                "min_dst_zkb_lost_volume",
+                x.lost_per_day,
+                config.sell_sell.sell_sell_zkb.min_dst_zkb_lost_volume,
+            );
+            if let Some(min_profit) = config.min_profit {
+                checks.above("min_profit", x.rough_profit, min_profit);
+            }
+            if let Some(filled_for_days) = x.filled_for_days {
+                checks.below(
+                    "max_filled_for_days_cutoff",
+                    filled_for_days,
+                    config.sell_sell.max_filled_for_days_cutoff,
+                );
+            }
+            disable_filters || checks.passed()
+        })
+        .sorted_unstable_by(|a, b| b.rough_profit.total_cmp(&a.rough_profit))
+        .collect::<Vec<_>>();
+    report.take(items, config.items_take, "items_take", |x| {
+        (x.market.desc.type_id, &x.market.desc.name)
+    })
+}
 
-            let src_mkt_orders = x.source.orders.clone();
-            let src_volume_on_market = src_mkt_orders.iter().sell_order_volume();
+fn evaluate(
+    config: &Config,
+    zkb_items: &ItemFrequencies,
+    x: SystemMarketsItemData,
+) -> Result<PairCalculatedDataSellSellZkb, SkipReason> {
+    let item_lose_popularity = *zkb_items.items.get(&x.desc.type_id).unwrap_or(&0);
+    let period_days = (zkb_items.period_seconds as f64) / 60. / 60. / 24.;
+    let lost_per_day = if period_days > 0. {
+        item_lose_popularity as f64 / period_days
+    } else {
+        0.
+    };
 
-            let dst_mkt_orders = x.destination.orders.clone();
-            let dst_volume_on_market: i32 = dst_mkt_orders.iter().sell_order_volume();
+    let src_mkt_orders = x.source.orders.clone();
+    let src_volume_on_market = src_mkt_orders.iter().sell_order_volume();
 
-            let src_avgs = averages(config, &x.source.history).or_else(|| {
-                log::debug!(
-                    "Item {} ({}) doesn't have any history in source.",
-                    x.desc.name,
-                    x.desc.type_id
-                );
-                None
-            });
-            let dst_avgs = averages(config, &x.destination.history).or_else(|| {
-                log::debug!(
-                    "Item {} ({}) doesn't have any history in destination.",
-                    x.desc.name,
-                    x.desc.type_id
-                );
-                None
-            })?;
+    let dst_mkt_orders = x.destination.orders.clone();
+    let dst_volume_on_market: i32 = dst_mkt_orders.iter().sell_order_volume();
 
-            let common = prepare_sell_sell(
-                config,
-                x,
-                lost_per_day,
-                src_volume_on_market,
-                src_avgs,
-                dst_volume_on_market,
-                dst_avgs,
-            );
-            Some(PairCalculatedDataSellSellZkb {
-                common,
-                lost_per_day,
-            })
-        })
-        .filter(|x| disable_filters || x.margin > config.margin_cutoff)
-        .filter(|x| {
-            disable_filters
-                || x.src_avgs.map(|x| x.volume).unwrap_or(0f64) > config.sell_sell.min_src_volume
-                    && x.lost_per_day > config.sell_sell.sell_sell_zkb.min_dst_zkb_lost_volume
-                    && config
-                        .min_profit
-                        .map_or(true, |min_prft| x.rough_profit > min_prft)
-        })
-        .filter(|x| {
-            disable_filters
-                || if let Some(filled_for_days) = x.filled_for_days {
-                    filled_for_days < config.sell_sell.max_filled_for_days_cutoff
-                } else {
-                    true
-                }
-        })
-        .sorted_unstable_by_key(|x| NotNan::new(-x.rough_profit).unwrap())
-        .take(config.items_take)
-        .collect::<Vec<_>>()
+    let src_avgs = averages(config, &x.source.history).or_else(|| {
+        log::debug!(
+            "Item {} ({}) doesn't have any history in source.",
+            x.desc.name,
+            x.desc.type_id
+        );
+        None
+    });
+    let dst_avgs =
+        averages(config, &x.destination.history).ok_or(SkipReason::NoDestinationHistory)?;
+
+    let common = prepare_sell_sell(
+        config,
+        x,
+        lost_per_day,
+        src_volume_on_market,
+        src_avgs,
+        dst_volume_on_market,
+        dst_avgs,
+    )?;
+    Ok(PairCalculatedDataSellSellZkb {
+        common,
+        lost_per_day,
+    })
 }
 
 pub fn make_table_sell_sell_zkb<'a, 'b>(
@@ -88,6 +115,8 @@ pub fn make_table_sell_sell_zkb<'a, 'b>(
     let rows = std::iter::once(Row::new(vec![
         TableCell::new("id"),
         TableCell::new("item name"),
+        TableCell::new("src hub"),
+        TableCell::new("list at"),
         TableCell::new("src prc"),
         TableCell::new("dst prc"),
         TableCell::new("expenses"),
@@ -103,11 +132,16 @@ pub fn make_table_sell_sell_zkb<'a, 'b>(
         TableCell::new("lost pr dy"),
     ]))
     .chain(good_items.iter().map(|it| {
-        let short_name =
-            it.market.desc.name[..(name_length.min(it.market.desc.name.len()))].to_owned();
+        let short_name = shorten(&it.market.desc.name, name_length);
         Row::new(vec![
             TableCell::new(format!("{}", it.market.desc.type_id)),
             TableCell::new(short_name),
+            TableCell::new(shorten(&it.source, name_length)),
+            TableCell::new(
+                it.list_at
+                    .as_ref()
+                    .map_or(String::new(), |x| shorten(x, name_length)),
+            ),
             TableCell::new(format!("{:.2}", it.src_buy_price)),
             TableCell::new(format!("{:.2}", it.dest_min_sell_price)),
             TableCell::new(format!("{:.2}", it.expenses)),
@@ -133,7 +167,9 @@ pub fn make_table_sell_sell_zkb<'a, 'b>(
     rows
 }
 
+#[derive(Serialize)]
 pub struct PairCalculatedDataSellSellZkb {
+    #[serde(flatten)]
     pub common: PairCalculatedDataSellSellCommon,
     pub lost_per_day: f64,
 }
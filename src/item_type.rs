@@ -1,7 +1,15 @@
-use rust_eveonline_esi::models::GetUniverseTypesTypeIdOk;
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate};
+use rust_eveonline_esi::models::{get_markets_region_id_orders_200_ok, GetUniverseTypesTypeIdOk};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    consts::{DATE_FMT, HISTORY_DAYS},
+    market_store::last_downtime,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketsRegionHistory {
     pub average: Option<f64>,
     pub date: String,
@@ -32,6 +40,51 @@ pub struct Order {
     pub volume_total: i32,
 }
 
+/// Where sell orders must be placed to fill a buy order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderRange {
+    Station,
+    SolarSystem,
+    Jumps(usize),
+    Region,
+}
+
+impl OrderRange {
+    /// Whether an item sold at `our_station` fills the buy order placed at `order_location`.
+    /// `jumps` is the distance between their systems, `None` if there is no route.
+    /// Orders are expected to be from the region of `our_station`.
+    pub fn covers(&self, order_location: i64, our_station: i64, jumps: Option<usize>) -> bool {
+        match *self {
+            OrderRange::Station => order_location == our_station,
+            OrderRange::SolarSystem => order_location == our_station || jumps == Some(0),
+            OrderRange::Jumps(range) => {
+                order_location == our_station || jumps.map_or(false, |x| x <= range)
+            }
+            OrderRange::Region => true,
+        }
+    }
+}
+
+impl From<get_markets_region_id_orders_200_ok::Range> for OrderRange {
+    fn from(x: get_markets_region_id_orders_200_ok::Range) -> Self {
+        use get_markets_region_id_orders_200_ok::Range;
+        match x {
+            Range::Station => OrderRange::Station,
+            Range::Solarsystem => OrderRange::SolarSystem,
+            Range::_1 => OrderRange::Jumps(1),
+            Range::_2 => OrderRange::Jumps(2),
+            Range::_3 => OrderRange::Jumps(3),
+            Range::_4 => OrderRange::Jumps(4),
+            Range::_5 => OrderRange::Jumps(5),
+            Range::_10 => OrderRange::Jumps(10),
+            Range::_20 => OrderRange::Jumps(20),
+            Range::_30 => OrderRange::Jumps(30),
+            Range::_40 => OrderRange::Jumps(40),
+            Range::Region => OrderRange::Region,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Copy, Clone)]
 pub struct ItemTypeAveraged {
     pub average: f64,
@@ -49,23 +102,43 @@ pub struct ItemHistoryDay {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketData {
-    pub history: Vec<ItemHistoryDay>,
+    /// One entry per day up to the last published one, `None` if nothing was traded that day.
+    /// Covers at least [`HISTORY_DAYS`] days.
+    pub history: Vec<Option<ItemHistoryDay>>,
     pub orders: Vec<Order>,
 }
 
 impl From<ItemType> for MarketData {
     fn from(x: ItemType) -> Self {
-        MarketData {
-            history: x
-                .history
-                .iter()
-                .map(|x| ItemHistoryDay {
+        let mut days = x
+            .history
+            .into_iter()
+            .filter_map(|x| {
+                let date = NaiveDate::parse_from_str(x.date.as_str(), DATE_FMT).ok()?;
+                let day = ItemHistoryDay {
                     average: x.average,
                     highest: x.highest,
                     lowest: x.lowest,
                     order_count: x.order_count,
                     volume: x.volume,
-                })
+                };
+                Some((date, day))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let last_published = last_downtime().naive_utc().date() - Duration::days(1);
+        let first_day = last_published - Duration::days(HISTORY_DAYS);
+        let first_day = days.keys().next().map_or(first_day, |&x| x.min(first_day));
+        let last_day = days
+            .keys()
+            .next_back()
+            .map_or(last_published, |&x| x.max(last_published));
+
+        MarketData {
+            history: first_day
+                .iter_days()
+                .take_while(|x| *x <= last_day)
+                .map(|x| days.remove(&x))
                 .collect(),
             orders: x.orders,
         }
@@ -83,7 +156,19 @@ pub struct SystemMarketsItem {
 pub struct SystemMarketsItemData {
     pub desc: TypeDescription,
     pub source: MarketData,
+    pub source_hub: SourceHub,
     pub destination: MarketData,
+    /// Station of the destination area with the least competition.
+    pub list_at: Option<String>,
+}
+
+/// Station the item is bought at with the costs that depend on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceHub {
+    pub name: String,
+    pub broker_fee: f64,
+    pub freight_cost_iskm3: f64,
+    pub freight_cost_collateral_percent: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -124,3 +209,35 @@ impl From<GetUniverseTypesTypeIdOk> for TypeDescription {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OrderRange;
+
+    const STATION: i64 = 60003760;
+    const OTHER_STATION: i64 = 60003757;
+
+    #[test]
+    fn station_range_covers_only_its_station() {
+        assert!(OrderRange::Station.covers(STATION, STATION, Some(0)));
+        assert!(!OrderRange::Station.covers(OTHER_STATION, STATION, Some(0)));
+    }
+
+    #[test]
+    fn system_range_covers_its_system() {
+        assert!(OrderRange::SolarSystem.covers(OTHER_STATION, STATION, Some(0)));
+        assert!(!OrderRange::SolarSystem.covers(OTHER_STATION, STATION, Some(1)));
+    }
+
+    #[test]
+    fn jumps_range_covers_up_to_its_jumps() {
+        assert!(OrderRange::Jumps(5).covers(OTHER_STATION, STATION, Some(5)));
+        assert!(!OrderRange::Jumps(5).covers(OTHER_STATION, STATION, Some(6)));
+        assert!(!OrderRange::Jumps(5).covers(OTHER_STATION, STATION, None));
+    }
+
+    #[test]
+    fn region_range_covers_everything() {
+        assert!(OrderRange::Region.covers(OTHER_STATION, STATION, None));
+    }
+}
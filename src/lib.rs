@@ -1,33 +1,124 @@
 pub mod auth;
 pub mod cached_data;
+pub mod cassette;
 pub mod cli;
 pub mod config;
 pub mod consts;
 pub mod datadump_service;
 pub mod error;
+pub mod fees;
 pub mod good_items;
 pub mod item_type;
 pub mod logger;
+pub mod market_data;
+pub mod market_store;
 pub mod order_ext;
+pub mod output;
 pub mod requests;
+pub mod route_graph;
 pub mod stat;
 pub mod zkb;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct StationIdData {
     pub station_id: StationId,
     pub system_id: i32,
     pub region_id: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Station as written in config.
+/// Ids skip the search by name, station is used as is when system and region are known too.
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
 pub struct Station {
+    #[serde(default)]
     pub is_citadel: bool,
+    #[serde(default)]
     pub name: String,
+    pub station_id: Option<i64>,
+    pub structure_id: Option<i64>,
+    pub system_id: Option<i32>,
+    pub region_id: Option<i32>,
 }
-#[derive(Clone, Copy)]
+
+impl Station {
+    pub fn is_citadel(&self) -> bool {
+        self.is_citadel || self.structure_id.is_some()
+    }
+
+    /// Name of the cached lookup of the station, made of its ids and name.
+    /// Unlike `Hash` it stays the same across builds.
+    pub fn cache_key(&self) -> String {
+        let id = |x: Option<i64>| x.map_or("-".to_string(), |x| x.to_string());
+        // names can contain anything, only letters and digits are kept in the file name
+        // and the checksum tells apart names that differ in the rest
+        let name = self
+            .name
+            .chars()
+            .map(|x| if x.is_alphanumeric() { x } else { '_' })
+            .collect::<String>();
+        format!(
+            "{}_{}_{}_{}_{}_{}_{:08x}",
+            self.is_citadel as u8,
+            id(self.station_id),
+            id(self.structure_id),
+            id(self.system_id.map(i64::from)),
+            id(self.region_id.map(i64::from)),
+            name,
+            crc32fast::hash(self.name.as_bytes())
+        )
+    }
+
+    /// Ids if nothing has to be looked up.
+    pub fn known_ids(&self) -> Option<StationIdData> {
+        Some(StationIdData {
+            station_id: StationId {
+                is_citadel: self.is_citadel(),
+                id: self.structure_id.or(self.station_id)?,
+            },
+            system_id: self.system_id?,
+            region_id: self.region_id?,
+        })
+    }
+}
+
+impl std::fmt::Display for Station {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.structure_id, self.station_id) {
+            _ if !self.name.is_empty() => write!(f, "{}", self.name),
+            (Some(id), _) => write!(f, "structure {}", id),
+            (None, Some(id)) => write!(f, "station {}", id),
+            (None, None) => write!(f, "unnamed station"),
+        }
+    }
+}
+
+/// Which orders around a station make up its market.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketScope {
+    /// Orders in the station and buy orders that reach it.
+    #[default]
+    Station,
+    /// Orders in every station and public structure of the station's system.
+    System,
+    Constellation,
+    Region,
+}
+
+impl MarketScope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarketScope::Station => "station",
+            MarketScope::System => "system",
+            MarketScope::Constellation => "constellation",
+            MarketScope::Region => "region",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct StationId {
     pub is_citadel: bool,
     pub id: i64,
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use futures::{future::join_all, stream, StreamExt};
+use itertools::Itertools;
+use tokio::join;
+
+use crate::{
+    consts::BUFFER_UNORDERED,
+    error::Result,
+    good_items::{help::SkipReason, report::FilterReport},
+    item_type::{ItemType, MarketData, SourceHub, SystemMarketsItemData, TypeDescription},
+    market_store::{last_downtime, MarketStore},
+    requests::market_data_source::MarketDataSource,
+    MarketScope, StationIdData,
+};
+
+/// Station whose orders are downloaded, with the systems of its area.
+pub struct MarketLocation {
+    pub station: StationIdData,
+    pub scope: MarketScope,
+    pub systems: Vec<i32>,
+}
+
+impl MarketLocation {
+    pub fn key(&self) -> (i64, MarketScope) {
+        (self.station.station_id.id, self.scope)
+    }
+}
+
+/// Orders and history are refreshed independently.
+/// Orders are downloaded into the store unless the last download is still fresh.
+/// History is downloaded only for types that weren't downloaded since the last downtime.
+/// Every station and region is downloaded once even if several routes use it.
+/// Returned history contains every day the store knows about.
+pub async fn load_market_data(
+    market: &dyn MarketDataSource,
+    store: &MarketStore,
+    all_types: &[i32],
+    locations: &[MarketLocation],
+    refresh_orders: bool,
+    refresh_history: bool,
+    orders_timeout: Option<Duration>,
+) -> Result<HashMap<(i64, MarketScope), HashMap<i32, MarketData>>> {
+    let locations = locations.iter().unique_by(|x| x.key()).collect::<Vec<_>>();
+    let regions = locations
+        .iter()
+        .map(|x| x.station.region_id)
+        .unique()
+        .collect::<Vec<_>>();
+
+    let fresh = locations
+        .iter()
+        .map(|location| {
+            let (station_id, scope) = location.key();
+            Ok(store
+                .last_orders_snapshot(station_id, scope)?
+                .filter(|x| !refresh_orders && x.is_fresh(orders_timeout)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // structures are only looked up when orders of an area are downloaded
+    let structure_systems =
+        if locations.iter().zip(&fresh).any(|(location, snapshot)| {
+            location.scope != MarketScope::Station && snapshot.is_none()
+        }) {
+            public_structure_systems(market, store).await?
+        } else {
+            HashMap::new()
+        };
+
+    let orders = locations.iter().zip(fresh).map(|(&location, snapshot)| {
+        let structure_systems = &structure_systems;
+        async move {
+            let (station_id, scope) = location.key();
+            let station = location.station;
+            let snapshot = match snapshot {
+                Some(snapshot) => {
+                    log::info!(
+                        "Orders of station {} ({}) loaded from the store",
+                        station_id,
+                        scope.name()
+                    );
+                    snapshot
+                }
+                None => {
+                    let orders = match scope {
+                        MarketScope::Station => market.get_orders_station(station).await?,
+                        _ => {
+                            let structures = structure_systems
+                                .iter()
+                                .filter(|(_, system)| {
+                                    system.map_or(false, |x| location.systems.contains(&x))
+                                })
+                                .map(|(&id, _)| id)
+                                .collect::<Vec<_>>();
+                            market
+                                .get_orders_area(station, scope, &location.systems, &structures)
+                                .await?
+                        }
+                    };
+                    let expires = market.market_data_expiry(station);
+                    store.save_orders(station_id, scope, expires, &orders)?
+                }
+            };
+            Result::Ok((location.key(), snapshot))
+        }
+    });
+    let history = regions.iter().map(|&region_id| async move {
+        let stale_types = if refresh_history {
+            all_types.to_vec()
+        } else {
+            store.stale_history_types(region_id, all_types, last_downtime())?
+        };
+        log::info!(
+            "Downloading history of {} out of {} types in region {}...",
+            stale_types.len(),
+            all_types.len(),
+            region_id
+        );
+        let history = market.history(&stale_types, region_id).await?;
+        store.save_history(region_id, &history)
+    });
+    let (snapshots, history) = join!(join_all(orders), join_all(history));
+    history.into_iter().collect::<Result<Vec<_>>>()?;
+    let snapshots = snapshots.into_iter().collect::<Result<HashMap<_, _>>>()?;
+
+    let history = regions
+        .iter()
+        .map(|&region_id| Ok((region_id, store.history(region_id, all_types)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    locations
+        .iter()
+        .map(|location| {
+            let mut orders = store
+                .orders(&snapshots[&location.key()])?
+                .into_iter()
+                .into_group_map_by(|x| x.type_id);
+            let history = &history[&location.station.region_id];
+            let data = all_types
+                .iter()
+                .map(|&id| {
+                    let item = ItemType {
+                        id,
+                        history: history.get(&id).cloned().unwrap_or_default(),
+                        orders: orders.remove(&id).unwrap_or_default(),
+                    };
+                    (id, item.into())
+                })
+                .collect::<HashMap<_, _>>();
+            Ok((location.key(), data))
+        })
+        .collect()
+}
+
+/// Systems of public structures with a market, `None` if the character can't see the structure.
+/// Structures don't move, so only the ones not looked up for a month are requested.
+async fn public_structure_systems(
+    market: &dyn MarketDataSource,
+    store: &MarketStore,
+) -> Result<HashMap<i64, Option<i32>>> {
+    let structures = market.public_market_structures().await?;
+    let mut known = store.structure_systems(Utc::now() - Duration::days(30))?;
+    let missing = structures
+        .iter()
+        .copied()
+        .filter(|x| !known.contains_key(x))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        log::info!("Looking up systems of {} structures...", missing.len());
+        let resolved = stream::iter(missing)
+            .map(|id| async move { Ok((id, market.structure_system(id).await?)) })
+            .buffer_unordered(BUFFER_UNORDERED)
+            .collect::<Vec<Result<_>>>()
+            .await
+            .into_iter()
+            .collect::<Result<HashMap<_, _>>>()?;
+        store.save_structure_systems(&resolved)?;
+        known.extend(resolved);
+    }
+    known.retain(|id, _| structures.contains(id));
+    Ok(known)
+}
+
+/// Every type is paired once for each source hub.
+/// Types without description or outside of `include_groups` and hubs without history of the type
+/// are rejected.
+pub fn route_pairs(
+    sources: &[(&HashMap<i32, MarketData>, SourceHub)],
+    destination: &HashMap<i32, MarketData>,
+    type_descriptions: &HashMap<i32, Option<TypeDescription>>,
+    group_ids: Option<&[i32]>,
+    report: &mut FilterReport,
+) -> Vec<SystemMarketsItemData> {
+    let mut pairs = Vec::new();
+    for (id, destination) in destination {
+        let desc = match type_descriptions.get(id).cloned().flatten() {
+            Some(x) => x,
+            None => {
+                report.skip(*id, "", SkipReason::NoDescription, false);
+                continue;
+            }
+        };
+
+        // include only specific groups
+        if let Some(ids) = group_ids {
+            if !desc
+                .market_group_id
+                .map(|x| ids.contains(&x))
+                .unwrap_or(false)
+            {
+                report.reject(desc.type_id, &desc.name, "include_groups", None, None);
+                continue;
+            }
+        }
+
+        for (source, hub) in sources {
+            let source = match source.get(id) {
+                Some(x) => x.clone(),
+                None => {
+                    report.skip(*id, &desc.name, SkipReason::NoSourceHistory, false);
+                    continue;
+                }
+            };
+            pairs.push(SystemMarketsItemData {
+                desc: desc.clone(),
+                source,
+                source_hub: hub.clone(),
+                destination: destination.clone(),
+                list_at: None,
+            });
+        }
+    }
+    pairs
+}
@@ -0,0 +1,452 @@
+use std::{collections::HashMap, path::Path};
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    consts::{DOWNTIME_HOUR, MARKET_STORE_SCHEMA_VERSION, ORDERS_DEFAULT_EXPIRY_MINUTES},
+    error::Result,
+    item_type::{MarketsRegionHistory, Order, TypeDescription},
+    MarketScope,
+};
+
+/// Market data kept between runs.
+/// History days are only ever added, so the store remembers more than ESI's 13 months.
+pub struct MarketStore {
+    conn: Connection,
+    /// Created by this run, nothing was downloaded into it before.
+    created: bool,
+}
+
+/// When orders of a station were downloaded.
+#[derive(Debug, Clone, Copy)]
+pub struct OrdersSnapshot {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl OrdersSnapshot {
+    /// Same rules as cached files: fixed `timeout` wins over the expiration date.
+    /// Without either the snapshot expires like ESI's market cache does.
+    pub fn is_fresh(&self, timeout: Option<chrono::Duration>) -> bool {
+        match (timeout, self.expires) {
+            (Some(timeout), _) => self.time + timeout >= Utc::now(),
+            (None, Some(expires)) => expires >= Utc::now(),
+            (None, None) => {
+                self.time + Duration::minutes(ORDERS_DEFAULT_EXPIRY_MINUTES) >= Utc::now()
+            }
+        }
+    }
+}
+
+impl MarketStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Store that is gone after the run, for replayed data.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(conn: Connection) -> Result<Self> {
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let mut created = version == 0;
+        if version == 1 {
+            // only order snapshots changed, downloaded history is kept
+            conn.execute_batch(
+                "ALTER TABLE order_snapshots ADD COLUMN scope TEXT NOT NULL DEFAULT 'station';",
+            )?;
+        } else if version != 0 && version != MARKET_STORE_SCHEMA_VERSION {
+            log::warn!(
+                "Market store has schema version {}, expected {}. Recreating it.",
+                version,
+                MARKET_STORE_SCHEMA_VERSION
+            );
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS history;
+                DROP TABLE IF EXISTS history_downloads;
+                DROP TABLE IF EXISTS orders;
+                DROP TABLE IF EXISTS order_snapshots;
+                DROP TABLE IF EXISTS type_descriptions;",
+            )?;
+            created = true;
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", MARKET_STORE_SCHEMA_VERSION)?;
+        Ok(Self { conn, created })
+    }
+
+    /// Whether the store was created or recreated by this run and is empty.
+    pub fn is_created(&self) -> bool {
+        self.created
+    }
+
+    /// Adds new history days and overwrites the ones already known.
+    /// Types are remembered as downloaded now even if they have no history
+    /// or it didn't change (`None`).
+    pub fn save_history(
+        &self,
+        region_id: i32,
+        history: &HashMap<i32, Option<Vec<MarketsRegionHistory>>>,
+    ) -> Result<()> {
+        let time = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT OR REPLACE INTO history
+                    (region_id, type_id, date, average, highest, lowest, order_count, volume)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            let mut download_statement = tx.prepare(
+                "INSERT OR REPLACE INTO history_downloads (region_id, type_id, time)
+                VALUES (?, ?, ?)",
+            )?;
+            for (type_id, days) in history {
+                download_statement.execute(params![region_id, type_id, time])?;
+                for day in days.iter().flatten() {
+                    statement.execute(params![
+                        region_id,
+                        type_id,
+                        day.date,
+                        day.average,
+                        day.highest,
+                        day.lowest,
+                        day.order_count,
+                        day.volume
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Types whose history wasn't downloaded since `since`.
+    pub fn stale_history_types(
+        &self,
+        region_id: i32,
+        type_ids: &[i32],
+        since: DateTime<Utc>,
+    ) -> Result<Vec<i32>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT type_id, time FROM history_downloads WHERE region_id = ?")?;
+        let downloads = statement
+            .query_map([region_id], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(type_ids
+            .iter()
+            .copied()
+            .filter(|id| {
+                downloads
+                    .get(id)
+                    .and_then(|x| parse_time(x))
+                    .map_or(true, |x| x < since)
+            })
+            .collect())
+    }
+
+    /// All known history of the types sorted by date.
+    pub fn history(
+        &self,
+        region_id: i32,
+        type_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<MarketsRegionHistory>>> {
+        let mut statement = self.conn.prepare(
+            "SELECT date, average, highest, lowest, order_count, volume
+            FROM history
+            WHERE region_id = ? AND type_id = ?
+            ORDER BY date",
+        )?;
+        type_ids
+            .iter()
+            .map(|&type_id| {
+                let history = statement
+                    .query_map([region_id, type_id], |row| {
+                        Ok(MarketsRegionHistory {
+                            date: row.get(0)?,
+                            average: row.get(1)?,
+                            highest: row.get(2)?,
+                            lowest: row.get(3)?,
+                            order_count: row.get(4)?,
+                            volume: row.get(5)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok((type_id, history))
+            })
+            .collect()
+    }
+
+    /// Saves orders of the station as a new snapshot and deletes older snapshots of the same scope.
+    pub fn save_orders(
+        &self,
+        station_id: i64,
+        scope: MarketScope,
+        expires: Option<DateTime<Utc>>,
+        orders: &[Order],
+    ) -> Result<OrdersSnapshot> {
+        let time = Utc::now();
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO order_snapshots (station_id, scope, time, expires) VALUES (?, ?, ?, ?)",
+            params![
+                station_id,
+                scope.name(),
+                time.to_rfc3339(),
+                expires.map(|x| x.to_rfc3339())
+            ],
+        )?;
+        let snapshot_id = tx.last_insert_rowid();
+        tx.execute(
+            "DELETE FROM orders WHERE snapshot_id IN (
+                SELECT id FROM order_snapshots WHERE station_id = ? AND scope = ? AND id < ?
+            )",
+            params![station_id, scope.name(), snapshot_id],
+        )?;
+        tx.execute(
+            "DELETE FROM order_snapshots WHERE station_id = ? AND scope = ? AND id < ?",
+            params![station_id, scope.name(), snapshot_id],
+        )?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO orders
+                    (snapshot_id, order_id, type_id, is_buy_order, price, volume_remain,
+                    volume_total, min_volume, location_id, duration, issued)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for order in orders {
+                statement.execute(params![
+                    snapshot_id,
+                    order.order_id,
+                    order.type_id,
+                    order.is_buy_order,
+                    order.price,
+                    order.volume_remain,
+                    order.volume_total,
+                    order.min_volume,
+                    order.location_id,
+                    order.duration,
+                    order.issued
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(OrdersSnapshot {
+            id: snapshot_id,
+            time,
+            expires,
+        })
+    }
+
+    pub fn last_orders_snapshot(
+        &self,
+        station_id: i64,
+        scope: MarketScope,
+    ) -> Result<Option<OrdersSnapshot>> {
+        let snapshot = self
+            .conn
+            .query_row(
+                "SELECT id, time, expires
+                FROM order_snapshots
+                WHERE station_id = ? AND scope = ?
+                ORDER BY id DESC
+                LIMIT 1",
+                params![station_id, scope.name()],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        Ok(snapshot.and_then(|(id, time, expires)| {
+            Some(OrdersSnapshot {
+                id,
+                time: parse_time(&time)?,
+                expires: expires.and_then(|x| parse_time(&x)),
+            })
+        }))
+    }
+
+    pub fn orders(&self, snapshot: &OrdersSnapshot) -> Result<Vec<Order>> {
+        let mut statement = self.conn.prepare(
+            "SELECT order_id, type_id, is_buy_order, price, volume_remain,
+                volume_total, min_volume, location_id, duration, issued
+            FROM orders
+            WHERE snapshot_id = ?",
+        )?;
+        let orders = statement
+            .query_map([snapshot.id], |row| {
+                Ok(Order {
+                    order_id: row.get(0)?,
+                    type_id: row.get(1)?,
+                    is_buy_order: row.get(2)?,
+                    price: row.get(3)?,
+                    volume_remain: row.get(4)?,
+                    volume_total: row.get(5)?,
+                    min_volume: row.get(6)?,
+                    location_id: row.get(7)?,
+                    duration: row.get(8)?,
+                    issued: row.get(9)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(orders)
+    }
+
+    /// Descriptions saved later than `since`. Types without a description are `None`.
+    pub fn type_descriptions(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<HashMap<i32, Option<TypeDescription>>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT type_id, data FROM type_descriptions WHERE time >= ?")?;
+        let rows = statement
+            .query_map([since.to_rfc3339()], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(type_id, data)| {
+                let desc = data.map(|x| serde_json::from_str(&x)).transpose()?;
+                Ok((type_id, desc))
+            })
+            .collect()
+    }
+
+    pub fn save_type_descriptions(
+        &self,
+        descriptions: &HashMap<i32, Option<TypeDescription>>,
+    ) -> Result<()> {
+        let time = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT OR REPLACE INTO type_descriptions
+                    (type_id, name, market_group_id, data, time)
+                VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for (type_id, desc) in descriptions {
+                statement.execute(params![
+                    type_id,
+                    desc.as_ref().map(|x| &x.name),
+                    desc.as_ref().and_then(|x| x.market_group_id),
+                    desc.as_ref().map(serde_json::to_string).transpose()?,
+                    time
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Systems of structures looked up later than `since`.
+    pub fn structure_systems(&self, since: DateTime<Utc>) -> Result<HashMap<i64, Option<i32>>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT structure_id, system_id FROM structure_systems WHERE time >= ?")?;
+        let systems = statement
+            .query_map([since.to_rfc3339()], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(systems)
+    }
+
+    pub fn save_structure_systems(&self, systems: &HashMap<i64, Option<i32>>) -> Result<()> {
+        let time = Utc::now().to_rfc3339();
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT OR REPLACE INTO structure_systems (structure_id, system_id, time)
+                VALUES (?, ?, ?)",
+            )?;
+            for (structure_id, system_id) in systems {
+                statement.execute(params![structure_id, system_id, time])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Start of the latest downtime. History downloaded before it lacks the last day.
+pub fn last_downtime() -> DateTime<Utc> {
+    let now = Utc::now();
+    let downtime = now.naive_utc().date().and_hms(DOWNTIME_HOUR, 0, 0);
+    let downtime = DateTime::from_utc(downtime, Utc);
+    if downtime > now {
+        downtime - chrono::Duration::days(1)
+    } else {
+        downtime
+    }
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS history (
+    region_id INTEGER NOT NULL,
+    type_id INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    average REAL,
+    highest REAL,
+    lowest REAL,
+    order_count INTEGER NOT NULL,
+    volume INTEGER NOT NULL,
+    PRIMARY KEY (region_id, type_id, date)
+);
+CREATE TABLE IF NOT EXISTS history_downloads (
+    region_id INTEGER NOT NULL,
+    type_id INTEGER NOT NULL,
+    time TEXT NOT NULL,
+    PRIMARY KEY (region_id, type_id)
+);
+CREATE TABLE IF NOT EXISTS order_snapshots (
+    id INTEGER PRIMARY KEY,
+    station_id INTEGER NOT NULL,
+    time TEXT NOT NULL,
+    expires TEXT,
+    scope TEXT NOT NULL DEFAULT 'station'
+);
+CREATE INDEX IF NOT EXISTS order_snapshots_station ON order_snapshots (station_id);
+CREATE TABLE IF NOT EXISTS orders (
+    snapshot_id INTEGER NOT NULL REFERENCES order_snapshots (id),
+    order_id INTEGER NOT NULL,
+    type_id INTEGER NOT NULL,
+    is_buy_order INTEGER NOT NULL,
+    price REAL NOT NULL,
+    volume_remain INTEGER NOT NULL,
+    volume_total INTEGER NOT NULL,
+    min_volume INTEGER NOT NULL,
+    location_id INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    issued TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, order_id)
+);
+CREATE TABLE IF NOT EXISTS type_descriptions (
+    type_id INTEGER PRIMARY KEY,
+    name TEXT,
+    market_group_id INTEGER,
+    data TEXT,
+    time TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS structure_systems (
+    structure_id INTEGER PRIMARY KEY,
+    system_id INTEGER,
+    time TEXT NOT NULL
+);
+";
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{collections::HashMap, ops::Deref};
 
 use crate::item_type::Order;
 
@@ -7,6 +7,11 @@ where
     It: Deref<Target = Order>,
 {
     fn sell_order_volume(self) -> i32;
+
+    /// Location with the least volume of sell orders out of `station` and the locations with sell orders.
+    /// `station` wins ties. Buy orders are left out, they can be placed outside of the area
+    /// and only reach into it by their range.
+    fn least_competed_location(self, station: i64) -> i64;
 }
 
 impl<'a, T, It> OrderIterExt<'a, It> for T
@@ -21,4 +26,15 @@ where
             .sum();
         market_volume
     }
+
+    fn least_competed_location(self, station: i64) -> i64 {
+        let mut volumes = HashMap::from([(station, 0)]);
+        for order in self.filter(|x| !x.is_buy_order) {
+            *volumes.entry(order.location_id).or_insert(0) += order.volume_remain;
+        }
+        volumes
+            .into_iter()
+            .min_by_key(|&(location, volume)| (volume, location != station, location))
+            .map_or(station, |(location, _)| location)
+    }
 }
@@ -0,0 +1,108 @@
+use serde::Serialize;
+use serde_json::Value;
+use term_table::{row::Row, TableBuilder};
+
+use crate::error::Result;
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "table" => Some(OutputFormat::Table),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// `data` is written as json, `csv_rows` as csv and `table` rows as a text table.
+    pub fn render<'a, D, R>(
+        self,
+        data: &D,
+        csv_rows: &[R],
+        table: impl FnOnce() -> Vec<Row<'a>>,
+    ) -> Result<String>
+    where
+        D: Serialize + ?Sized,
+        R: Serialize,
+    {
+        Ok(match self {
+            OutputFormat::Table => TableBuilder::new().rows(table()).build().render(),
+            OutputFormat::Json => serde_json::to_string_pretty(data)?,
+            OutputFormat::Csv => to_csv(csv_rows)?,
+        })
+    }
+}
+
+/// Nested fields become columns named by their path like `dst_avgs.volume`.
+/// Columns are the union of fields of all rows, missing ones are left empty.
+pub fn to_csv<T: Serialize>(rows: &[T]) -> Result<String> {
+    let rows = rows
+        .iter()
+        .map(|x| {
+            let mut fields = Vec::new();
+            flatten("", &serde_json::to_value(x)?, &mut fields);
+            Ok(fields)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut columns = Vec::<String>::new();
+    for (name, _) in rows.iter().flatten() {
+        if !columns.contains(name) {
+            columns.push(name.clone());
+        }
+    }
+
+    let mut csv = String::new();
+    push_record(&mut csv, columns.iter().map(|x| x.as_str()));
+    for row in &rows {
+        push_record(
+            &mut csv,
+            columns.iter().map(|column| {
+                row.iter()
+                    .find(|(name, _)| name == column)
+                    .map_or("", |(_, value)| value.as_str())
+            }),
+        );
+    }
+    Ok(csv)
+}
+
+fn flatten(prefix: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&name, value, fields);
+            }
+        }
+        Value::Null => (),
+        Value::String(x) => fields.push((prefix.to_string(), x.clone())),
+        x => fields.push((prefix.to_string(), x.to_string())),
+    }
+}
+
+fn push_record<'a>(csv: &mut String, fields: impl Iterator<Item = &'a str>) {
+    let fields = fields
+        .map(|x| {
+            if x.contains(&[',', '"', '\n', '\r'][..]) {
+                format!("\"{}\"", x.replace('"', "\"\""))
+            } else {
+                x.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    csv.push_str(&fields.join(","));
+    csv.push('\n');
+}
@@ -1,4 +1,8 @@
+pub mod conditional;
 pub mod error;
+pub mod error_limit;
+pub mod fixture;
+pub mod market_data_source;
 pub mod paged_all;
 pub mod retry;
 pub mod service;
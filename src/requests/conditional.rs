@@ -0,0 +1,240 @@
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use reqwest::{
+    header::{self, HeaderMap},
+    StatusCode,
+};
+use rust_eveonline_esi::apis::configuration::Configuration;
+use serde::de::DeserializeOwned;
+
+use super::{
+    error::{EsiApiError, Result},
+    error_limit::ErrorLimiter,
+};
+use crate::cached_data::{CachedResponse, ResponseCache};
+
+pub struct EsiResponse<T> {
+    pub data: T,
+    pub headers: EsiHeaders,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EsiHeaders {
+    pub etag: Option<String>,
+    pub expires: Option<DateTime<Utc>>,
+    pub last_modified: Option<DateTime<Utc>>,
+    /// Number of pages of a paginated endpoint.
+    pub pages: Option<i32>,
+    pub error_limit_remain: Option<i32>,
+    pub error_limit_reset: Option<u64>,
+}
+
+impl EsiHeaders {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            etag: header_str(headers, header::ETAG).map(|x| x.to_string()),
+            expires: header_str(headers, header::EXPIRES)
+                .and_then(|x| DateTime::parse_from_rfc2822(x).ok())
+                .map(|x| x.with_timezone(&Utc)),
+            last_modified: header_str(headers, header::LAST_MODIFIED)
+                .and_then(|x| DateTime::parse_from_rfc2822(x).ok())
+                .map(|x| x.with_timezone(&Utc)),
+            pages: header_str(headers, header::HeaderName::from_static("x-pages"))
+                .and_then(|x| x.parse().ok()),
+            error_limit_remain: header_str(
+                headers,
+                header::HeaderName::from_static("x-esi-error-limit-remain"),
+            )
+            .and_then(|x| x.parse().ok()),
+            error_limit_reset: header_str(
+                headers,
+                header::HeaderName::from_static("x-esi-error-limit-reset"),
+            )
+            .and_then(|x| x.parse().ok()),
+        }
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|x| x.to_str().ok())
+}
+
+/// GET request to ESI that reuses the previous response for the same url and parameters
+/// until it expires. After that the ETag of the previous response is sent
+/// and if ESI answers with 304 the previous body is reused.
+pub async fn get_conditional<T: DeserializeOwned>(
+    config: &Configuration,
+    cache: &ResponseCache,
+    limiter: &ErrorLimiter,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<EsiResponse<T>> {
+    let key = cache_key(path, query);
+    if let Some((body, cached)) = cache.get_fresh(&key).and_then(with_body) {
+        log::debug!("{} is cached until {:?}", key, cached.expires);
+        return Ok(EsiResponse {
+            data: serde_json::from_str(body.as_str())?,
+            headers: cached_headers(&cached),
+        });
+    }
+    let cached = cache.get(&key).and_then(with_body);
+
+    let etag = cached.as_ref().and_then(|(_, x)| x.etag.as_deref());
+    let (status, headers, content) = send(config, limiter, path, query, etag).await?;
+    match (status, cached) {
+        (StatusCode::NOT_MODIFIED, Some((body, cached))) => {
+            log::debug!("{} not modified", key);
+            let data = serde_json::from_str(body.as_str())?;
+            let headers = EsiHeaders {
+                etag: headers.etag.or(cached.etag),
+                last_modified: headers.last_modified.or(cached.last_modified),
+                pages: headers.pages.or(cached.pages),
+                ..headers
+            };
+            cache.insert(key, cached_response(&headers, Some(body)));
+            Ok(EsiResponse { data, headers })
+        }
+        (status, _) if status.is_success() => {
+            let data = serde_json::from_str(content.as_str())?;
+            if headers.etag.is_some() || headers.expires.is_some() {
+                cache.insert(key, cached_response(&headers, Some(content)));
+            }
+            Ok(EsiResponse { data, headers })
+        }
+        (status, _) => Err(EsiApiError::response(status, content)),
+    }
+}
+
+/// Like [`get_conditional`], but the body isn't cached, only the ETag and expiration date.
+/// The caller keeps the data itself, `None` means it didn't change since the previous response.
+pub async fn get_if_modified<T: DeserializeOwned>(
+    config: &Configuration,
+    cache: &ResponseCache,
+    limiter: &ErrorLimiter,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<Option<EsiResponse<T>>> {
+    let key = cache_key(path, query);
+    if let Some(cached) = cache.get_fresh(&key) {
+        log::debug!("{} is unchanged until {:?}", key, cached.expires);
+        return Ok(None);
+    }
+    let cached = cache.get(&key);
+
+    let etag = cached.as_ref().and_then(|x| x.etag.as_deref());
+    let (status, headers, content) = send(config, limiter, path, query, etag).await?;
+    match (status, cached) {
+        (StatusCode::NOT_MODIFIED, Some(cached)) => {
+            log::debug!("{} not modified", key);
+            let headers = EsiHeaders {
+                etag: headers.etag.or(cached.etag),
+                last_modified: headers.last_modified.or(cached.last_modified),
+                pages: headers.pages.or(cached.pages),
+                ..headers
+            };
+            cache.insert(key, cached_response(&headers, None));
+            Ok(None)
+        }
+        (status, _) if status.is_success() => {
+            let data = serde_json::from_str(content.as_str())?;
+            if headers.etag.is_some() || headers.expires.is_some() {
+                cache.insert(key, cached_response(&headers, None));
+            }
+            Ok(Some(EsiResponse { data, headers }))
+        }
+        (status, _) => Err(EsiApiError::response(status, content)),
+    }
+}
+
+/// GET request that is always downloaded. Only the expiration date is cached,
+/// so it is known when the data can change.
+pub async fn get_expiring<T: DeserializeOwned>(
+    config: &Configuration,
+    cache: &ResponseCache,
+    limiter: &ErrorLimiter,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<EsiResponse<T>> {
+    let (status, headers, content) = send(config, limiter, path, query, None).await?;
+    if !status.is_success() {
+        return Err(EsiApiError::response(status, content));
+    }
+    let data = serde_json::from_str(content.as_str())?;
+    if headers.expires.is_some() {
+        // without ETag the response is evicted once it expires
+        let headers = EsiHeaders {
+            etag: None,
+            ..headers.clone()
+        };
+        cache.insert(cache_key(path, query), cached_response(&headers, None));
+    }
+    Ok(EsiResponse { data, headers })
+}
+
+/// Base path differs between live and recorded runs, so it isn't part of the key.
+fn cache_key(path: &str, query: &[(&str, String)]) -> String {
+    format!(
+        "{}?{}",
+        path,
+        query.iter().map(|(k, v)| format!("{}={}", k, v)).join("&")
+    )
+}
+
+/// Responses cached without body can't be returned again.
+fn with_body(cached: CachedResponse) -> Option<(String, CachedResponse)> {
+    Some((cached.body.clone()?, cached))
+}
+
+fn cached_response(headers: &EsiHeaders, body: Option<String>) -> CachedResponse {
+    CachedResponse {
+        etag: headers.etag.clone(),
+        body,
+        expires: headers.expires,
+        last_modified: headers.last_modified,
+        pages: headers.pages,
+    }
+}
+
+fn cached_headers(cached: &CachedResponse) -> EsiHeaders {
+    EsiHeaders {
+        etag: cached.etag.clone(),
+        expires: cached.expires,
+        last_modified: cached.last_modified,
+        pages: cached.pages,
+        ..Default::default()
+    }
+}
+
+/// Sends the request with `etag` as `If-None-Match` and keeps the error limit up to date.
+async fn send(
+    config: &Configuration,
+    limiter: &ErrorLimiter,
+    path: &str,
+    query: &[(&str, String)],
+    etag: Option<&str>,
+) -> Result<(StatusCode, EsiHeaders, String)> {
+    let url = format!("{}{}", config.base_path, path);
+    let mut req = config.client.get(url.as_str()).query(query);
+    if let Some(user_agent) = &config.user_agent {
+        req = req.header(header::USER_AGENT, user_agent.as_str());
+    }
+    if let Some(token) = &config.oauth_access_token {
+        req = req.bearer_auth(token);
+    }
+    if let Some(etag) = etag {
+        req = req.header(header::IF_NONE_MATCH, etag);
+    }
+
+    limiter.wait().await;
+    let response = req.send().await?;
+    let status = response.status();
+    let headers = EsiHeaders::from_headers(response.headers());
+    match (headers.error_limit_remain, headers.error_limit_reset) {
+        (Some(remain), Some(reset)) => limiter.update(remain, reset),
+        // error limited without headers, wait for the longest possible window
+        _ if status == StatusCode::from_u16(420).unwrap() => limiter.update(0, 60),
+        _ => (),
+    }
+    let content = response.text().await?;
+    Ok((status, headers, content))
+}
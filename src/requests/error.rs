@@ -1,18 +1,6 @@
 use std::fmt::Display;
 
 use reqwest::StatusCode;
-use rust_eveonline_esi::apis::{
-    self,
-    killmails_api::GetKillmailsKillmailIdKillmailHashError,
-    market_api::{
-        GetMarketsGroupsError, GetMarketsRegionIdHistoryError, GetMarketsRegionIdOrdersError,
-        GetMarketsRegionIdTypesError, GetMarketsStructuresStructureIdError,
-    },
-    routes_api::GetRouteOriginDestinationError,
-    search_api::GetCharactersCharacterIdSearchError,
-    universe_api::GetUniverseTypesTypeIdError,
-    ResponseContent,
-};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, EsiApiError>;
@@ -33,135 +21,74 @@ impl Display for EsiApiError {
 
 #[derive(Error, Debug)]
 enum EsiApiErrorEnum {
-    #[error("market group")]
-    MarketGroups(#[from] apis::Error<GetMarketsGroupsError>),
-    #[error("regional market")]
-    MarketOrders(#[from] apis::Error<GetMarketsRegionIdOrdersError>),
-    #[error("structure search")]
-    StructSearch(#[from] apis::Error<GetCharactersCharacterIdSearchError>),
-    #[error("structure market")]
-    StructureMarket(#[from] apis::Error<GetMarketsStructuresStructureIdError>),
-    #[error("region type ids")]
-    RegionTypeId(#[from] apis::Error<GetMarketsRegionIdTypesError>),
-    #[error("route")]
-    Route(#[from] apis::Error<GetRouteOriginDestinationError>),
-    #[error("killmails")]
-    KillmailData(#[from] apis::Error<GetKillmailsKillmailIdKillmailHashError>),
-    #[error("type ids")]
-    UniverseTypesTypeId(#[from] apis::Error<GetUniverseTypesTypeIdError>),
-    #[error("market history")]
-    MarketHistory(#[from] apis::Error<GetMarketsRegionIdHistoryError>),
+    #[error("fixture doesn't contain {0}")]
+    MissingFixture(String),
+    #[error("request")]
+    Request(#[from] reqwest::Error),
+    #[error("response deserialization")]
+    Deserialization(#[from] serde_json::Error),
+    #[error("unexpected response: {0}")]
+    Response(String),
+    #[error("pages kept changing during download, gave up after {0} restarts")]
+    InconsistentPages(u32),
+    #[error(transparent)]
+    RetriesExhausted(#[from] RetriesExhausted),
 }
 
-impl From<apis::Error<GetMarketsGroupsError>> for EsiApiError {
-    fn from(x: apis::Error<GetMarketsGroupsError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        EsiApiError {
-            internal: x.into(),
-            status: code,
-        }
-    }
-}
-
-impl From<apis::Error<GetMarketsRegionIdOrdersError>> for EsiApiError {
-    fn from(x: apis::Error<GetMarketsRegionIdOrdersError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        EsiApiError {
-            internal: x.into(),
-            status: code,
-        }
-    }
-}
-
-impl From<apis::Error<GetCharactersCharacterIdSearchError>> for EsiApiError {
-    fn from(x: apis::Error<GetCharactersCharacterIdSearchError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        EsiApiError {
-            internal: x.into(),
-            status: code,
-        }
-    }
+#[derive(Error, Debug)]
+#[error("gave up after {attempts} attempts: {last_error}")]
+pub struct RetriesExhausted {
+    pub attempts: u32,
+    pub last_error: String,
 }
 
-impl From<apis::Error<GetMarketsStructuresStructureIdError>> for EsiApiError {
-    fn from(x: apis::Error<GetMarketsStructuresStructureIdError>) -> Self {
-        // this particular endpoint returns 500 code on invalid page
+impl EsiApiError {
+    pub fn response(status: StatusCode, content: String) -> Self {
+        // some endpoints return 400 or 500 code on invalid page
         // so we have to extract error message
-        let code = match &x {
-            apis::Error::ResponseError(ResponseContent {
-                entity: Some(GetMarketsStructuresStructureIdError::Status400(r)),
-                ..
-            }) if r.error.contains("Undefined 404 response") => StatusCode::NOT_FOUND,
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        let status = if content.contains("Undefined 404 response") {
+            StatusCode::NOT_FOUND
+        } else {
+            status
         };
         EsiApiError {
-            internal: x.into(),
-            status: code,
+            internal: EsiApiErrorEnum::Response(content),
+            status,
         }
     }
-}
 
-impl From<apis::Error<GetMarketsRegionIdTypesError>> for EsiApiError {
-    fn from(x: apis::Error<GetMarketsRegionIdTypesError>) -> Self {
-        // this particular endpoint returns 500 code on invalid page
-        // so we have to extract error message
-        let code = match &x {
-            apis::Error::ResponseError(ResponseContent {
-                entity: Some(GetMarketsRegionIdTypesError::Status400(r)),
-                ..
-            }) if r.error.contains("Undefined 404 response") => StatusCode::NOT_FOUND,
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+    pub fn retries_exhausted(attempts: u32, last_error: Option<EsiApiError>) -> Self {
+        let status = last_error
+            .as_ref()
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, |x| x.status);
         EsiApiError {
-            internal: x.into(),
-            status: code,
+            internal: RetriesExhausted {
+                attempts,
+                last_error: last_error.map_or("retry requested".to_string(), |x| x.to_string()),
+            }
+            .into(),
+            status,
         }
     }
-}
 
-impl From<apis::Error<GetRouteOriginDestinationError>> for EsiApiError {
-    fn from(x: apis::Error<GetRouteOriginDestinationError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+    pub fn inconsistent_pages(restarts: u32) -> Self {
         EsiApiError {
-            internal: x.into(),
-            status: code,
+            internal: EsiApiErrorEnum::InconsistentPages(restarts),
+            status: StatusCode::SERVICE_UNAVAILABLE,
         }
     }
-}
 
-impl From<apis::Error<GetKillmailsKillmailIdKillmailHashError>> for EsiApiError {
-    fn from(x: apis::Error<GetKillmailsKillmailIdKillmailHashError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+    pub fn missing_fixture(what: impl Into<String>) -> Self {
         EsiApiError {
-            internal: x.into(),
-            status: code,
+            internal: EsiApiErrorEnum::MissingFixture(what.into()),
+            status: StatusCode::NOT_FOUND,
         }
     }
 }
 
-impl From<apis::Error<GetUniverseTypesTypeIdError>> for EsiApiError {
-    fn from(x: apis::Error<GetUniverseTypesTypeIdError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+impl From<reqwest::Error> for EsiApiError {
+    fn from(x: reqwest::Error) -> Self {
+        let code = x.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
         EsiApiError {
             internal: x.into(),
             status: code,
@@ -169,15 +96,11 @@ impl From<apis::Error<GetUniverseTypesTypeIdError>> for EsiApiError {
     }
 }
 
-impl From<apis::Error<GetMarketsRegionIdHistoryError>> for EsiApiError {
-    fn from(x: apis::Error<GetMarketsRegionIdHistoryError>) -> Self {
-        let code = match &x {
-            apis::Error::ResponseError(x) => x.status,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+impl From<serde_json::Error> for EsiApiError {
+    fn from(x: serde_json::Error) -> Self {
         EsiApiError {
             internal: x.into(),
-            status: code,
+            status: StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
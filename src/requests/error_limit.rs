@@ -0,0 +1,77 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::consts::{ERROR_LIMIT, ERROR_LIMIT_THRESHOLD};
+
+/// Tracks ESI error limit reported in `X-ESI-Error-Limit-*` headers
+/// and holds back all requests when few errors are left in the current window.
+pub struct ErrorLimiter {
+    state: Mutex<ErrorLimitState>,
+}
+
+struct ErrorLimitState {
+    remain: i32,
+    reset_at: Option<Instant>,
+}
+
+impl Default for ErrorLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorLimiter {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ErrorLimitState {
+                remain: ERROR_LIMIT,
+                reset_at: None,
+            }),
+        }
+    }
+
+    pub fn update(&self, remain: i32, reset_seconds: u64) {
+        let reset_at = Instant::now() + Duration::from_secs(reset_seconds);
+        let mut state = self.state.lock().unwrap();
+
+        // responses can arrive out of order, so inside one window
+        // only the smallest value is current
+        let new_window = state
+            .reset_at
+            .map_or(true, |x| reset_at > x + Duration::from_secs(1));
+        state.remain = if new_window {
+            remain
+        } else {
+            state.remain.min(remain)
+        };
+        state.reset_at = Some(reset_at);
+    }
+
+    /// Waits until the error limit window is reset if there are too few errors left.
+    pub async fn wait(&self) {
+        loop {
+            let wait_for = {
+                let state = self.state.lock().unwrap();
+                match state.reset_at {
+                    Some(reset_at) if state.remain <= ERROR_LIMIT_THRESHOLD => reset_at
+                        .checked_duration_since(Instant::now())
+                        .map(|x| (state.remain, x)),
+                    _ => None,
+                }
+            };
+            match wait_for {
+                Some((remain, duration)) if !duration.is_zero() => {
+                    log::warn!(
+                        "Only {} errors left before ESI error limit. Waiting {:?} for reset...",
+                        remain,
+                        duration
+                    );
+                    tokio::time::sleep(duration).await;
+                }
+                _ => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::{collections::HashMap, path::Path};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{
+    error::{EsiApiError, Result},
+    market_data_source::MarketDataSource,
+    service::Killmail,
+};
+use crate::{
+    error::StationResolveError,
+    fees::CharacterFees,
+    item_type::{MarketsRegionHistory, Order, TypeDescription},
+    MarketScope, Station, StationIdData,
+};
+
+/// Saved market state that can be served back instead of ESI.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    /// Keyed by station name or id as written in config.
+    pub stations: HashMap<String, StationIdData>,
+    pub region_types: HashMap<i32, Vec<i32>>,
+    pub type_descriptions: HashMap<i32, Option<TypeDescription>>,
+    pub orders: HashMap<i64, Vec<Order>>,
+    /// Orders of the area around the station, keyed by the station id and the scope of the area.
+    #[serde(default)]
+    pub area_orders: HashMap<i64, HashMap<MarketScope, Vec<Order>>>,
+    /// region id -> type id -> history
+    pub history: HashMap<i32, HashMap<i32, Vec<MarketsRegionHistory>>>,
+    pub killmails: HashMap<i32, Option<Killmail>>,
+    #[serde(default)]
+    pub character_fees: Option<CharacterFees>,
+}
+
+impl MarketSnapshot {
+    pub fn from_file_json<P: AsRef<Path>>(path: P) -> crate::error::Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        let snapshot: MarketSnapshot = serde_json::from_str(str.as_ref())?;
+
+        Ok(snapshot)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> crate::error::Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+pub struct FixtureMarketDataSource {
+    snapshot: MarketSnapshot,
+}
+
+impl FixtureMarketDataSource {
+    pub fn new(snapshot: MarketSnapshot) -> Self {
+        Self { snapshot }
+    }
+
+    pub fn from_file_json<P: AsRef<Path>>(path: P) -> crate::error::Result<Self> {
+        Ok(Self::new(MarketSnapshot::from_file_json(path)?))
+    }
+}
+
+#[async_trait]
+impl MarketDataSource for FixtureMarketDataSource {
+    async fn find_region_id_station(
+        &self,
+        station: Station,
+        _character_id: i32,
+    ) -> std::result::Result<StationIdData, StationResolveError> {
+        self.snapshot
+            .stations
+            .get(&station.to_string())
+            .copied()
+            .ok_or_else(|| StationResolveError::NotFound(station.to_string()))
+    }
+
+    async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>> {
+        self.snapshot
+            .region_types
+            .get(&region_id)
+            .cloned()
+            .ok_or_else(|| EsiApiError::missing_fixture(format!("types of region {}", region_id)))
+    }
+
+    async fn get_type_description(&self, type_id: i32) -> Result<Option<TypeDescription>> {
+        Ok(self
+            .snapshot
+            .type_descriptions
+            .get(&type_id)
+            .cloned()
+            .flatten())
+    }
+
+    async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
+        Ok(self
+            .snapshot
+            .orders
+            .get(&station.station_id.id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn get_orders_area(
+        &self,
+        station: StationIdData,
+        scope: MarketScope,
+        _systems: &[i32],
+        _structures: &[i64],
+    ) -> Result<Vec<Order>> {
+        Ok(self
+            .snapshot
+            .area_orders
+            .get(&station.station_id.id)
+            .and_then(|x| x.get(&scope))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Orders of structures are part of the recorded area orders.
+    async fn public_market_structures(&self) -> Result<Vec<i64>> {
+        Ok(Vec::new())
+    }
+
+    async fn structure_system(&self, structure_id: i64) -> Result<Option<i32>> {
+        Err(EsiApiError::missing_fixture(format!(
+            "system of structure {}",
+            structure_id
+        )))
+    }
+
+    async fn history(
+        &self,
+        item_types: &[i32],
+        region_id: i32,
+    ) -> Result<HashMap<i32, Option<Vec<MarketsRegionHistory>>>> {
+        let region_history = self.snapshot.history.get(&region_id);
+        Ok(item_types
+            .iter()
+            .map(|&id| {
+                let history = region_history
+                    .and_then(|x| x.get(&id))
+                    .cloned()
+                    .unwrap_or_default();
+                (id, Some(history))
+            })
+            .collect())
+    }
+
+    fn market_data_expiry(&self, _station: StationIdData) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    async fn get_killmail_items_frequency(
+        &self,
+        killmail_id: i32,
+        _hash: String,
+    ) -> Result<Option<Killmail>> {
+        Ok(self.snapshot.killmails.get(&killmail_id).cloned().flatten())
+    }
+
+    async fn get_character_fees(&self, _character_id: i32) -> Result<CharacterFees> {
+        self.snapshot
+            .character_fees
+            .clone()
+            .ok_or_else(|| EsiApiError::missing_fixture("character fees"))
+    }
+}
+
+/// Passes requests through to another source and remembers every answer,
+/// so a live run can be saved as a fixture.
+pub struct RecordingMarketDataSource<'a> {
+    inner: &'a dyn MarketDataSource,
+    snapshot: Mutex<MarketSnapshot>,
+}
+
+impl<'a> RecordingMarketDataSource<'a> {
+    pub fn new(inner: &'a dyn MarketDataSource) -> Self {
+        Self {
+            inner,
+            snapshot: Mutex::new(MarketSnapshot::default()),
+        }
+    }
+
+    pub async fn save_json<P: AsRef<Path>>(&self, path: P) -> crate::error::Result<()> {
+        self.snapshot.lock().await.save_json(path)
+    }
+}
+
+#[async_trait]
+impl<'a> MarketDataSource for RecordingMarketDataSource<'a> {
+    async fn find_region_id_station(
+        &self,
+        station: Station,
+        character_id: i32,
+    ) -> std::result::Result<StationIdData, StationResolveError> {
+        let name = station.to_string();
+        let res = self
+            .inner
+            .find_region_id_station(station, character_id)
+            .await?;
+        self.snapshot.lock().await.stations.insert(name, res);
+        Ok(res)
+    }
+
+    async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>> {
+        let res = self.inner.get_all_item_types(region_id).await?;
+        self.snapshot
+            .lock()
+            .await
+            .region_types
+            .insert(region_id, res.clone());
+        Ok(res)
+    }
+
+    async fn get_type_description(&self, type_id: i32) -> Result<Option<TypeDescription>> {
+        let res = self.inner.get_type_description(type_id).await?;
+        self.snapshot
+            .lock()
+            .await
+            .type_descriptions
+            .insert(type_id, res.clone());
+        Ok(res)
+    }
+
+    async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
+        let res = self.inner.get_orders_station(station).await?;
+        self.snapshot
+            .lock()
+            .await
+            .orders
+            .insert(station.station_id.id, res.clone());
+        Ok(res)
+    }
+
+    async fn get_orders_area(
+        &self,
+        station: StationIdData,
+        scope: MarketScope,
+        systems: &[i32],
+        structures: &[i64],
+    ) -> Result<Vec<Order>> {
+        let res = self
+            .inner
+            .get_orders_area(station, scope, systems, structures)
+            .await?;
+        self.snapshot
+            .lock()
+            .await
+            .area_orders
+            .entry(station.station_id.id)
+            .or_default()
+            .insert(scope, res.clone());
+        Ok(res)
+    }
+
+    async fn public_market_structures(&self) -> Result<Vec<i64>> {
+        self.inner.public_market_structures().await
+    }
+
+    async fn structure_system(&self, structure_id: i64) -> Result<Option<i32>> {
+        self.inner.structure_system(structure_id).await
+    }
+
+    async fn history(
+        &self,
+        item_types: &[i32],
+        region_id: i32,
+    ) -> Result<HashMap<i32, Option<Vec<MarketsRegionHistory>>>> {
+        let res = self.inner.history(item_types, region_id).await?;
+        // recording runs download everything, unchanged history can't be recorded anyway
+        self.snapshot
+            .lock()
+            .await
+            .history
+            .entry(region_id)
+            .or_default()
+            .extend(
+                res.iter()
+                    .filter_map(|(&id, history)| Some((id, history.clone()?))),
+            );
+        Ok(res)
+    }
+
+    fn market_data_expiry(&self, station: StationIdData) -> Option<DateTime<Utc>> {
+        self.inner.market_data_expiry(station)
+    }
+
+    async fn get_killmail_items_frequency(
+        &self,
+        killmail_id: i32,
+        hash: String,
+    ) -> Result<Option<Killmail>> {
+        let res = self
+            .inner
+            .get_killmail_items_frequency(killmail_id, hash)
+            .await?;
+        self.snapshot
+            .lock()
+            .await
+            .killmails
+            .insert(killmail_id, res.clone());
+        Ok(res)
+    }
+
+    async fn get_character_fees(&self, character_id: i32) -> Result<CharacterFees> {
+        let res = self.inner.get_character_fees(character_id).await?;
+        self.snapshot.lock().await.character_fees = Some(res.clone());
+        Ok(res)
+    }
+}
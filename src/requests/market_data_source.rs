@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{
+    error::Result,
+    service::{EsiRequestsService, Killmail},
+};
+use crate::{
+    error::StationResolveError,
+    fees::CharacterFees,
+    item_type::{MarketsRegionHistory, Order, TypeDescription},
+    MarketScope, Station, StationIdData,
+};
+
+/// Everything the trading pipeline needs to know about the market.
+/// Implemented by the live ESI client and by recorded fixtures.
+#[async_trait]
+pub trait MarketDataSource: Sync {
+    async fn find_region_id_station(
+        &self,
+        station: Station,
+        character_id: i32,
+    ) -> std::result::Result<StationIdData, StationResolveError>;
+
+    async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>>;
+
+    async fn get_type_description(&self, type_id: i32) -> Result<Option<TypeDescription>>;
+
+    async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>>;
+
+    /// Orders of the station, of every station in `systems` and of the `structures`.
+    /// `systems` and `structures` make up the area of `scope` around the station.
+    async fn get_orders_area(
+        &self,
+        station: StationIdData,
+        scope: MarketScope,
+        systems: &[i32],
+        structures: &[i64],
+    ) -> Result<Vec<Order>>;
+
+    /// Ids of public structures with a market.
+    async fn public_market_structures(&self) -> Result<Vec<i64>>;
+
+    /// System of the structure, `None` if the character can't see it.
+    async fn structure_system(&self, structure_id: i64) -> Result<Option<i32>>;
+
+    /// History of the types in the region as ESI returns it, days without trades are missing.
+    /// `None` if the history of the type didn't change since it was last downloaded.
+    async fn history(
+        &self,
+        item_types: &[i32],
+        region_id: i32,
+    ) -> Result<HashMap<i32, Option<Vec<MarketsRegionHistory>>>>;
+
+    /// When orders of the station downloaded so far stop being current.
+    fn market_data_expiry(&self, station: StationIdData) -> Option<DateTime<Utc>>;
+
+    async fn get_killmail_items_frequency(
+        &self,
+        killmail_id: i32,
+        hash: String,
+    ) -> Result<Option<Killmail>>;
+
+    async fn get_character_fees(&self, character_id: i32) -> Result<CharacterFees>;
+}
+
+#[async_trait]
+impl<'a> MarketDataSource for EsiRequestsService<'a> {
+    async fn find_region_id_station(
+        &self,
+        station: Station,
+        character_id: i32,
+    ) -> std::result::Result<StationIdData, StationResolveError> {
+        EsiRequestsService::find_region_id_station(self, station, character_id).await
+    }
+
+    async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>> {
+        EsiRequestsService::get_all_item_types(self, region_id).await
+    }
+
+    async fn get_type_description(&self, type_id: i32) -> Result<Option<TypeDescription>> {
+        Ok(self.get_item_stuff(type_id).await?.map(|x| x.into()))
+    }
+
+    async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
+        EsiRequestsService::get_orders_station(self, station).await
+    }
+
+    async fn get_orders_area(
+        &self,
+        station: StationIdData,
+        _scope: MarketScope,
+        systems: &[i32],
+        structures: &[i64],
+    ) -> Result<Vec<Order>> {
+        EsiRequestsService::get_orders_area(self, station, systems, structures).await
+    }
+
+    async fn public_market_structures(&self) -> Result<Vec<i64>> {
+        EsiRequestsService::public_market_structures(self).await
+    }
+
+    async fn structure_system(&self, structure_id: i64) -> Result<Option<i32>> {
+        EsiRequestsService::structure_system(self, structure_id).await
+    }
+
+    async fn history(
+        &self,
+        item_types: &[i32],
+        region_id: i32,
+    ) -> Result<HashMap<i32, Option<Vec<MarketsRegionHistory>>>> {
+        EsiRequestsService::history(self, item_types, region_id).await
+    }
+
+    fn market_data_expiry(&self, station: StationIdData) -> Option<DateTime<Utc>> {
+        EsiRequestsService::market_data_expiry(self, station)
+    }
+
+    async fn get_killmail_items_frequency(
+        &self,
+        killmail_id: i32,
+        hash: String,
+    ) -> Result<Option<Killmail>> {
+        EsiRequestsService::get_killmail_items_frequency(self, killmail_id, hash).await
+    }
+
+    async fn get_character_fees(&self, character_id: i32) -> Result<CharacterFees> {
+        EsiRequestsService::get_character_fees(self, character_id).await
+    }
+}
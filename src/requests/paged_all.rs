@@ -1,195 +1,107 @@
 use std::{fmt::Debug, future::Future};
 
 use reqwest::StatusCode;
-use rust_eveonline_esi::{
-    apis::{
-        killmails_api::GetKillmailsKillmailIdKillmailHashSuccess,
-        market_api::{
-            GetMarketsRegionIdHistorySuccess, GetMarketsRegionIdOrdersSuccess,
-            GetMarketsRegionIdTypesSuccess, GetMarketsStructuresStructureIdSuccess,
-        },
-        routes_api::GetRouteOriginDestinationSuccess,
-        search_api::GetCharactersCharacterIdSearchSuccess,
-        universe_api::{
-            GetUniverseStationsStationIdSuccess, GetUniverseStructuresStructureIdSuccess,
-            GetUniverseTypesTypeIdSuccess,
-        },
-    },
-    models::{
-        GetCharactersCharacterIdSearchOk, GetKillmailsKillmailIdKillmailHashOk,
-        GetMarketsRegionIdHistory200Ok, GetMarketsRegionIdOrders200Ok,
-        GetMarketsStructuresStructureId200Ok, GetUniverseStationsStationIdOk,
-        GetUniverseStructuresStructureIdOk, GetUniverseTypesTypeIdOk,
-    },
-};
 
-use crate::requests::retry::{self, Retry};
+use futures::{stream, StreamExt};
+use itertools::Itertools;
+
+use crate::{
+    consts::{BUFFER_UNORDERED, PAGES_RESTARTS},
+    requests::retry::{self, Retry, RetryPolicy},
+};
 
-use super::error::EsiApiError;
+use super::{conditional::EsiResponse, error::EsiApiError};
 
-pub async fn get_all_pages<Ret, F, T>(get: F) -> Result<Vec<T>, super::error::EsiApiError>
+/// Downloads every page of a paginated endpoint.
+/// Page count is taken from `X-Pages` of the first page and the rest are downloaded concurrently.
+/// If the pages were modified in the middle of the download it is started over.
+pub async fn get_all_pages<Ret, F, T>(
+    policy: &RetryPolicy,
+    get: F,
+) -> Result<Vec<T>, super::error::EsiApiError>
 where
     F: Fn(i32) -> Ret,
-    Ret: Future<Output = Result<Vec<T>, super::error::EsiApiError>>,
+    Ret: Future<Output = Result<EsiResponse<Vec<T>>, super::error::EsiApiError>>,
     T: Debug,
 {
-    let mut all_types = Vec::new();
-    let mut page = 1;
+    let mut restarts = 0;
     loop {
-        let types = retry::retry_smart(|| async {
-            match get(page).await {
-                Ok(x) => Ok(Retry::Success(x)),
-
-                // 404 means that page is empty
-                Err(EsiApiError {
-                    status: StatusCode::NOT_FOUND,
-                    ..
-                }) => Ok(Retry::Success(Vec::new())),
+        let first = get_page(policy, &get, 1).await?;
+        let pages = match first.headers.pages {
+            Some(pages) => {
+                let mut rest = stream::iter(2..=pages)
+                    .map(|page| {
+                        let get = &get;
+                        async move { Ok((page, get_page(policy, get, page).await?)) }
+                    })
+                    .buffer_unordered(BUFFER_UNORDERED)
+                    .collect::<Vec<Result<_, EsiApiError>>>()
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()?;
+                rest.sort_unstable_by_key(|x| x.0);
+                std::iter::once(first)
+                    .chain(rest.into_iter().map(|x| x.1))
+                    .collect::<Vec<_>>()
+            }
+            // no page count, request pages until an empty one
+            None => {
+                let mut pages = vec![first];
+                while !pages.last().unwrap().data.is_empty() {
+                    pages.push(get_page(policy, &get, pages.len() as i32 + 1).await?);
+                }
+                pages
+            }
+        };
 
-                Err(e) => Err(e),
+        let modified = pages
+            .iter()
+            .filter_map(|x| x.headers.last_modified)
+            .unique()
+            .count();
+        // pages from the old snapshot expire when the new one is published,
+        // so they are downloaded again instead of taken from the response cache
+        if modified > 1 {
+            if restarts == PAGES_RESTARTS {
+                return Err(EsiApiError::inconsistent_pages(PAGES_RESTARTS));
             }
-        })
-        .await?;
-        let mut types = types.unwrap_or_else(|| {
-            log::warn!("Max retry count exceeded and error wasn't resolved.");
-            Vec::new()
-        });
-        if types.is_empty() {
-            break;
+            restarts += 1;
+            log::warn!(
+                "Pages were modified during download. Starting over ({}/{})...",
+                restarts,
+                PAGES_RESTARTS
+            );
+            continue;
         }
-        all_types.append(&mut types);
 
-        page += 1;
+        return Ok(pages.into_iter().flat_map(|x| x.data).collect());
     }
-    Ok(all_types)
 }
 
-pub trait OnlyOk<T, E>: Sized {
-    fn into_ok(self) -> Result<T, E>;
-}
-
-impl OnlyOk<Vec<i32>, GetMarketsRegionIdTypesSuccess> for GetMarketsRegionIdTypesSuccess {
-    fn into_ok(self) -> Result<Vec<i32>, GetMarketsRegionIdTypesSuccess> {
-        if let GetMarketsRegionIdTypesSuccess::Status200(types) = self {
-            Ok(types)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<Vec<GetMarketsRegionIdHistory200Ok>, GetMarketsRegionIdHistorySuccess>
-    for GetMarketsRegionIdHistorySuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<Vec<GetMarketsRegionIdHistory200Ok>, GetMarketsRegionIdHistorySuccess> {
-        if let GetMarketsRegionIdHistorySuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<GetUniverseTypesTypeIdOk, GetUniverseTypesTypeIdSuccess>
-    for GetUniverseTypesTypeIdSuccess
-{
-    fn into_ok(self) -> Result<GetUniverseTypesTypeIdOk, GetUniverseTypesTypeIdSuccess> {
-        if let GetUniverseTypesTypeIdSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-
-impl OnlyOk<GetCharactersCharacterIdSearchOk, GetCharactersCharacterIdSearchSuccess>
-    for GetCharactersCharacterIdSearchSuccess
+async fn get_page<Ret, F, T>(
+    policy: &RetryPolicy,
+    get: &F,
+    page: i32,
+) -> Result<EsiResponse<Vec<T>>, EsiApiError>
+where
+    F: Fn(i32) -> Ret,
+    Ret: Future<Output = Result<EsiResponse<Vec<T>>, EsiApiError>>,
 {
-    fn into_ok(
-        self,
-    ) -> Result<GetCharactersCharacterIdSearchOk, GetCharactersCharacterIdSearchSuccess> {
-        if let GetCharactersCharacterIdSearchSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
+    retry::retry_smart(policy, || async {
+        match get(page).await {
+            Ok(x) => Ok(Retry::Success(x)),
 
-impl OnlyOk<GetUniverseStructuresStructureIdOk, GetUniverseStructuresStructureIdSuccess>
-    for GetUniverseStructuresStructureIdSuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<GetUniverseStructuresStructureIdOk, GetUniverseStructuresStructureIdSuccess> {
-        if let GetUniverseStructuresStructureIdSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<GetUniverseStationsStationIdOk, GetUniverseStationsStationIdSuccess>
-    for GetUniverseStationsStationIdSuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<GetUniverseStationsStationIdOk, GetUniverseStationsStationIdSuccess> {
-        if let GetUniverseStationsStationIdSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<Vec<GetMarketsStructuresStructureId200Ok>, GetMarketsStructuresStructureIdSuccess>
-    for GetMarketsStructuresStructureIdSuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<Vec<GetMarketsStructuresStructureId200Ok>, GetMarketsStructuresStructureIdSuccess>
-    {
-        if let GetMarketsStructuresStructureIdSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<Vec<GetMarketsRegionIdOrders200Ok>, GetMarketsRegionIdOrdersSuccess>
-    for GetMarketsRegionIdOrdersSuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<Vec<GetMarketsRegionIdOrders200Ok>, GetMarketsRegionIdOrdersSuccess> {
-        if let GetMarketsRegionIdOrdersSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
-impl OnlyOk<GetKillmailsKillmailIdKillmailHashOk, GetKillmailsKillmailIdKillmailHashSuccess>
-    for GetKillmailsKillmailIdKillmailHashSuccess
-{
-    fn into_ok(
-        self,
-    ) -> Result<GetKillmailsKillmailIdKillmailHashOk, GetKillmailsKillmailIdKillmailHashSuccess>
-    {
-        if let GetKillmailsKillmailIdKillmailHashSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
-        }
-    }
-}
+            // 404 means that page is empty
+            Err(EsiApiError {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => Ok(Retry::Success(EsiResponse {
+                data: Vec::new(),
+                headers: Default::default(),
+            })),
 
-impl OnlyOk<Vec<i32>, GetRouteOriginDestinationSuccess> for GetRouteOriginDestinationSuccess {
-    fn into_ok(self) -> Result<Vec<i32>, GetRouteOriginDestinationSuccess> {
-        if let GetRouteOriginDestinationSuccess::Status200(ok) = self {
-            Ok(ok)
-        } else {
-            Err(self)
+            Err(e) => Err(e),
         }
-    }
+    })
+    .await
 }
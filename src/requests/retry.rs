@@ -1,78 +1,166 @@
 use std::time::Duration;
 
-use super::error::{EsiApiError, Result};
-use crate::consts::RETRIES;
+use super::error::{EsiApiError, Result, RetriesExhausted};
 use futures::Future;
+use rand::Rng;
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// How failed requests are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts including the first one.
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    /// Delay is multiplied by this after every attempt.
+    pub backoff_factor: f64,
+    pub max_delay_ms: u64,
+    /// Random part of the delay, 0.2 means +-20%.
+    pub jitter: f64,
+    /// Error statuses that are retried. Errors with other statuses are returned immediately.
+    pub statuses: Vec<StatusRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusRule {
+    pub status: u16,
+    pub max_attempts: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 100,
+            backoff_factor: 2.,
+            max_delay_ms: 30_000,
+            jitter: 0.2,
+            statuses: vec![
+                // error limited, error limiter holds the request until reset
+                StatusRule {
+                    status: 420,
+                    max_attempts: None,
+                    base_delay_ms: Some(0),
+                },
+                // common errors for ccp servers
+                StatusRule::new(StatusCode::BAD_GATEWAY),
+                StatusRule::new(StatusCode::SERVICE_UNAVAILABLE),
+                StatusRule::new(StatusCode::GATEWAY_TIMEOUT),
+            ],
+        }
+    }
+}
+
+impl StatusRule {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status: status.as_u16(),
+            max_attempts: None,
+            base_delay_ms: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn rule(&self, status: StatusCode) -> Option<&StatusRule> {
+        self.statuses.iter().find(|x| x.status == status.as_u16())
+    }
+
+    fn max_attempts(&self, rule: Option<&StatusRule>) -> u32 {
+        rule.and_then(|x| x.max_attempts)
+            .unwrap_or(self.max_attempts)
+    }
+
+    /// Delay before the attempt following `attempt`.
+    fn delay(&self, rule: Option<&StatusRule>, attempt: u32) -> Duration {
+        let base = rule
+            .and_then(|x| x.base_delay_ms)
+            .unwrap_or(self.base_delay_ms) as f64;
+        let delay =
+            (base * self.backoff_factor.powi(attempt as i32 - 1)).min(self.max_delay_ms as f64);
+        let jitter = self.jitter.clamp(0., 1.);
+        let jitter = rand::thread_rng().gen_range(-jitter..=jitter);
+        Duration::from_secs_f64(delay * (1. + jitter) / 1000.)
+    }
+}
 
 #[track_caller]
-pub fn retry_smart<T, Fut, F>(func: F) -> impl Future<Output = Result<Option<T>>>
+pub fn retry_smart<'a, T, Fut, F>(
+    policy: &'a RetryPolicy,
+    func: F,
+) -> impl Future<Output = Result<T>> + 'a
 where
-    Fut: Future<Output = Result<Retry<T>>>,
-    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Retry<T>>> + 'a,
+    F: Fn() -> Fut + 'a,
+    T: 'a,
 {
     let caller = std::panic::Location::caller();
     async move {
-        let res = retry_simple(|| async {
-            let out = func().await;
-            match out {
-                Ok(Retry::Success(x)) => Ok(Retry::Success(x)),
-                Ok(Retry::Retry) => Ok(Retry::Retry),
-
-                // error limited
-                Err(e @ EsiApiError { status, .. })
-                    if status == StatusCode::from_u16(420).unwrap() =>
-                {
-                    log::warn!(
-                        "[{}] Error limited: {}. Retrying in 60 seconds...",
-                        caller,
-                        e
-                    );
-                    tokio::time::sleep(Duration::from_secs_f32(60.)).await;
-                    Ok(Retry::Retry)
-                }
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (rule, error) = match func().await {
+                Ok(Retry::Success(x)) => return Ok(x),
+                Ok(Retry::Retry) => (None, None),
+                Err(e) => match policy.rule(e.status) {
+                    Some(rule) => (Some(rule), Some(e)),
+                    None => return Err(e),
+                },
+            };
 
-                // common errors for ccp servers
-                Err(EsiApiError {
-                    status:
-                        status @ (StatusCode::BAD_GATEWAY
-                        | StatusCode::SERVICE_UNAVAILABLE
-                        | StatusCode::GATEWAY_TIMEOUT),
-                    ..
-                }) => {
-                    log::warn!("[{}] Error: {}. Retrying...", caller, status);
-                    Ok(Retry::Retry)
-                }
-
-                Err(e) => Err(e),
+            let max_attempts = policy.max_attempts(rule);
+            let reason = error.as_ref().map_or("Retry requested".to_string(), |x| {
+                format!("Error {}: {}", x.status, x)
+            });
+            if attempt >= max_attempts {
+                log::error!(
+                    "[{}] {}. Giving up after {} attempts.",
+                    caller,
+                    reason,
+                    attempt
+                );
+                return Err(EsiApiError::retries_exhausted(attempt, error));
             }
-        })
-        .await?;
-        Ok(res)
+
+            let delay = policy.delay(rule, attempt);
+            log::warn!(
+                "[{}] {}. Retrying in {:?} ({}/{})...",
+                caller,
+                reason,
+                delay,
+                attempt,
+                max_attempts
+            );
+            tokio::time::sleep(delay).await;
+        }
     }
 }
 
-pub async fn retry_simple<T, Fut, F, E>(func: F) -> std::result::Result<Option<T>, E>
+pub async fn retry_simple<T, Fut, F, E>(policy: &RetryPolicy, func: F) -> std::result::Result<T, E>
 where
     Fut: Future<Output = std::result::Result<Retry<T>, E>>,
     F: Fn() -> Fut,
+    E: From<RetriesExhausted>,
 {
-    let mut retries = 0;
+    let mut attempt = 0;
     loop {
-        let out = func().await?;
-        break Ok(match out {
-            Retry::Retry => {
-                if retries > RETRIES {
-                    None
-                } else {
-                    retries += 1;
-                    // don't make too many retries sequentially
-                    tokio::time::sleep(Duration::from_secs_f32(0.1)).await;
-                    continue;
+        attempt += 1;
+        match func().await? {
+            Retry::Success(v) => break Ok(v),
+            Retry::Retry if attempt >= policy.max_attempts => {
+                break Err(RetriesExhausted {
+                    attempts: attempt,
+                    last_error: "retry requested".to_string(),
                 }
+                .into())
+            }
+            Retry::Retry => {
+                // don't make too many retries sequentially
+                tokio::time::sleep(policy.delay(None, attempt)).await;
             }
-            Retry::Success(v) => Some(v),
-        });
+        }
     }
 }
 
@@ -1,388 +1,330 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    consts::DATE_FMT,
-    item_type::MarketsRegionHistory,
-    requests::{paged_all::get_all_pages, retry::Retry},
+    cached_data::ResponseCache,
+    item_type::{MarketsRegionHistory, OrderRange},
+    requests::{
+        paged_all::get_all_pages,
+        retry::{Retry, RetryPolicy},
+    },
     Station, StationIdData,
 };
 use crate::{
-    consts::{self, BUFFER_UNORDERED},
-    item_type::ItemType,
-    requests::paged_all::OnlyOk,
+    consts::{self, BUFFER_UNORDERED, STATION_CANDIDATES},
+    error::StationResolveError,
+    fees::CharacterFees,
     requests::retry,
+    route_graph::{RouteGraph, RoutePreference},
     StationId,
 };
-use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 
-use super::error::{EsiApiError, Result};
+use super::{
+    conditional::{self, EsiResponse},
+    error::{EsiApiError, Result},
+    error_limit::ErrorLimiter,
+};
 use crate::item_type::Order;
-use crate::stat::MedianStat;
 
 use futures::{stream, StreamExt};
 use itertools::Itertools;
 use ordered_float::NotNan;
 use rust_eveonline_esi::{
-    apis::{
-        configuration::Configuration,
-        killmails_api::{self, GetKillmailsKillmailIdKillmailHashParams},
-        market_api::{
-            self, GetMarketsRegionIdHistoryParams, GetMarketsRegionIdOrdersParams,
-            GetMarketsRegionIdTypesParams, GetMarketsStructuresStructureIdParams,
-        },
-        routes_api::{self, GetRouteOriginDestinationParams},
-        search_api::{get_characters_character_id_search, GetCharactersCharacterIdSearchParams},
-        universe_api::{
-            self, GetUniverseConstellationsConstellationIdParams,
-            GetUniverseConstellationsConstellationIdSuccess, GetUniverseStationsStationIdParams,
-            GetUniverseStructuresStructureIdParams, GetUniverseSystemsSystemIdParams,
-            GetUniverseSystemsSystemIdSuccess, GetUniverseTypesTypeIdParams,
-        },
-    },
+    apis::configuration::Configuration,
     models::{
-        get_markets_region_id_orders_200_ok, GetKillmailsKillmailIdKillmailHashItem,
-        GetKillmailsKillmailIdKillmailHashItemsItem, GetMarketsRegionIdOrders200Ok,
-        GetUniverseTypesTypeIdOk,
+        GetCharactersCharacterIdSearchOk, GetCharactersCharacterIdSkillsOk,
+        GetCharactersCharacterIdStandings200Ok, GetKillmailsKillmailIdKillmailHashItem,
+        GetKillmailsKillmailIdKillmailHashItemsItem, GetKillmailsKillmailIdKillmailHashOk,
+        GetMarketsRegionIdHistory200Ok, GetMarketsRegionIdOrders200Ok,
+        GetMarketsStructuresStructureId200Ok, GetUniverseConstellationsConstellationIdOk,
+        GetUniverseStationsStationIdOk, GetUniverseStructuresStructureIdOk,
+        GetUniverseSystemsSystemIdOk, GetUniverseTypesTypeIdOk,
     },
 };
-
-use tokio::sync::{Mutex, RwLock};
+use serde::de::DeserializeOwned;
 
 pub struct EsiRequestsService<'a> {
     pub config: &'a Configuration,
+    pub responses: &'a ResponseCache,
+    pub retry_policy: &'a RetryPolicy,
+    pub route_graph: &'a RouteGraph,
+    /// Routes of buy order ranges.
+    pub route_preference: RoutePreference,
+    pub avoid_systems: &'a [i32],
+    pub limiter: ErrorLimiter,
 }
 impl<'a> EsiRequestsService<'a> {
-    pub fn new(config: &'a Configuration) -> Self {
-        Self { config }
+    pub fn new(
+        config: &'a Configuration,
+        responses: &'a ResponseCache,
+        retry_policy: &'a RetryPolicy,
+        route_graph: &'a RouteGraph,
+        route_preference: RoutePreference,
+        avoid_systems: &'a [i32],
+    ) -> Self {
+        Self {
+            config,
+            responses,
+            retry_policy,
+            route_graph,
+            route_preference,
+            avoid_systems,
+            limiter: ErrorLimiter::new(),
+        }
+    }
+
+    async fn get_conditional<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<EsiResponse<T>> {
+        conditional::get_conditional(self.config, self.responses, &self.limiter, path, query).await
+    }
+
+    async fn get_if_modified<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<Option<EsiResponse<T>>> {
+        conditional::get_if_modified(self.config, self.responses, &self.limiter, path, query).await
+    }
+
+    async fn get_expiring<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<EsiResponse<T>> {
+        conditional::get_expiring(self.config, self.responses, &self.limiter, path, query).await
     }
 
     pub async fn find_region_id_station(
         &self,
         station: Station,
         character_id: i32,
-    ) -> Result<StationIdData> {
-        // find system id
-        let station_id = if station.is_citadel {
-            get_characters_character_id_search(
-                self.config,
-                GetCharactersCharacterIdSearchParams {
-                    categories: vec!["structure".to_string()],
-                    character_id,
-                    search: station.name.to_string(),
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                    strict: None,
-                    token: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .structure
-            .unwrap()[0]
-        } else {
-            get_characters_character_id_search(
-                self.config,
-                GetCharactersCharacterIdSearchParams {
-                    character_id,
-                    token: None,
-                    categories: vec!["station".to_string()],
-                    search: station.name.to_string(),
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                    strict: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .station
-            .unwrap()
-            .into_iter()
-            .next()
-            .unwrap() as i64
-        };
-        let system_id = if station.is_citadel {
-            universe_api::get_universe_structures_structure_id(
-                self.config,
-                GetUniverseStructuresStructureIdParams {
-                    structure_id: station_id,
-                    datasource: None,
-                    if_none_match: None,
-                    token: None,
-                },
-            )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .solar_system_id
-        } else {
-            universe_api::get_universe_stations_station_id(
-                self.config,
-                GetUniverseStationsStationIdParams {
-                    station_id: station_id as i32,
-                    datasource: None,
-                    if_none_match: None,
-                },
-            )
-            .await
-            .unwrap()
-            .entity
-            .unwrap()
-            .into_ok()
-            .unwrap()
-            .system_id
+    ) -> std::result::Result<StationIdData, StationResolveError> {
+        let esi_err = |e| StationResolveError::esi(&station.to_string(), e);
+        let is_citadel = station.is_citadel();
+
+        // find station id
+        let (station_id, system_id) = match station.structure_id.or(station.station_id) {
+            Some(id) => match self
+                .get_station_location(is_citadel, id)
+                .await
+                .map_err(esi_err)?
+            {
+                Some((_, system_id)) => (id, system_id),
+                None => return Err(StationResolveError::NoDockingAccess(station.to_string())),
+            },
+            None => self.search_station(&station, character_id).await?,
         };
 
         // get system constellation
-        let constellation = if let GetUniverseSystemsSystemIdSuccess::Status200(jita_const) =
-            universe_api::get_universe_systems_system_id(
-                self.config,
-                GetUniverseSystemsSystemIdParams {
-                    system_id,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
+        let constellation = self
+            .get_conditional::<GetUniverseSystemsSystemIdOk>(
+                &format!("/universe/systems/{}/", system_id),
+                &[],
             )
             .await
-            .unwrap()
-            .entity
-            .unwrap()
-        {
-            jita_const.constellation_id
-        } else {
-            panic!();
-        };
+            .map_err(esi_err)?
+            .data
+            .constellation_id;
 
         // get system region
-        let region = if let GetUniverseConstellationsConstellationIdSuccess::Status200(ok) =
-            universe_api::get_universe_constellations_constellation_id(
-                self.config,
-                GetUniverseConstellationsConstellationIdParams {
-                    constellation_id: constellation,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
+        let region = self
+            .get_conditional::<GetUniverseConstellationsConstellationIdOk>(
+                &format!("/universe/constellations/{}/", constellation),
+                &[],
             )
             .await
-            .unwrap()
-            .entity
-            .unwrap()
-        {
-            ok.region_id
-        } else {
-            panic!();
-        };
+            .map_err(esi_err)?
+            .data
+            .region_id;
+
         Ok(StationIdData {
             station_id: StationId {
-                is_citadel: station.is_citadel,
+                is_citadel,
                 id: station_id,
             },
             system_id,
             region_id: region,
         })
     }
-    pub async fn get_item_stuff(&self, id: i32) -> Result<Option<GetUniverseTypesTypeIdOk>> {
-        let res = retry::retry_smart(|| async {
-            let res = universe_api::get_universe_types_type_id(
-                self.config,
-                GetUniverseTypesTypeIdParams {
-                    type_id: id,
-                    accept_language: None,
-                    datasource: None,
-                    if_none_match: None,
-                    language: None,
-                },
+
+    /// Station id and system id of the station found by name.
+    async fn search_station(
+        &self,
+        station: &Station,
+        character_id: i32,
+    ) -> std::result::Result<(i64, i32), StationResolveError> {
+        let esi_err = |e| StationResolveError::esi(&station.name, e);
+
+        let category = if station.is_citadel {
+            "structure"
+        } else {
+            "station"
+        };
+        let found = self
+            .get_conditional::<GetCharactersCharacterIdSearchOk>(
+                &format!("/characters/{}/search/", character_id),
+                &[
+                    ("categories", category.to_string()),
+                    ("search", station.name.clone()),
+                ],
             )
-            .await?
-            .entity
-            .unwrap();
-            Ok(Retry::Success(res.into_ok().unwrap()))
-        })
-        .await?;
+            .await
+            .map_err(esi_err)?
+            .data;
+        let ids = if station.is_citadel {
+            found.structure.unwrap_or_default()
+        } else {
+            found
+                .station
+                .unwrap_or_default()
+                .into_iter()
+                .map(i64::from)
+                .collect()
+        };
 
-        Ok(res)
+        let candidates = stream::iter(ids.into_iter().take(STATION_CANDIDATES))
+            .map(|id| async move {
+                let location = self.get_station_location(station.is_citadel, id).await;
+                location.map(|x| x.map(|(name, system_id)| (id, name, system_id)))
+            })
+            .buffer_unordered(BUFFER_UNORDERED)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map_err(esi_err)?;
+        let no_access = candidates.iter().any(|x| x.is_none());
+        let mut candidates = candidates.into_iter().flatten().collect::<Vec<_>>();
+
+        // search matches substrings, so exact name wins
+        let exact = candidates
+            .iter()
+            .position(|x| x.1.eq_ignore_ascii_case(station.name.as_str()));
+        let (station_id, _, system_id) = match exact {
+            Some(idx) => candidates.swap_remove(idx),
+            None if candidates.len() == 1 => candidates.remove(0),
+            None if candidates.is_empty() && no_access => {
+                return Err(StationResolveError::NoDockingAccess(station.name.clone()))
+            }
+            None if candidates.is_empty() => {
+                return Err(StationResolveError::NotFound(station.name.clone()))
+            }
+            None => {
+                return Err(StationResolveError::Ambiguous {
+                    name: station.name.clone(),
+                    candidates: candidates.into_iter().map(|x| x.1).sorted().collect(),
+                })
+            }
+        };
+        Ok((station_id, system_id))
     }
 
-    pub async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
-        // download all orders
-        log::info!("Downloading region orders...");
-        let pages: Vec<GetMarketsRegionIdOrders200Ok> = get_all_pages(|page| async move {
-            let orders = market_api::get_markets_region_id_orders(
-                self.config,
-                GetMarketsRegionIdOrdersParams {
-                    order_type: "all".to_string(),
-                    region_id: station.region_id,
-                    datasource: None,
-                    if_none_match: None,
-                    page: Some(page),
-                    type_id: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
+    /// Name and system of a station or structure.
+    /// `None` if the character has no access to the structure.
+    async fn get_station_location(
+        &self,
+        is_citadel: bool,
+        id: i64,
+    ) -> Result<Option<(String, i32)>> {
+        if is_citadel {
+            let res = self
+                .get_conditional::<GetUniverseStructuresStructureIdOk>(
+                    &format!("/universe/structures/{}/", id),
+                    &[],
+                )
+                .await;
+            match res {
+                Ok(x) => Ok(Some((x.data.name, x.data.solar_system_id))),
+                Err(EsiApiError {
+                    status: StatusCode::FORBIDDEN,
+                    ..
+                }) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            let res = self
+                .get_conditional::<GetUniverseStationsStationIdOk>(
+                    &format!("/universe/stations/{}/", id),
+                    &[],
+                )
+                .await?;
+            Ok(Some((res.data.name, res.data.system_id)))
+        }
+    }
 
-            Ok(orders.into_ok().unwrap())
+    pub async fn get_item_stuff(&self, id: i32) -> Result<Option<GetUniverseTypesTypeIdOk>> {
+        let res = retry::retry_smart(self.retry_policy, || async {
+            let res = self
+                .get_conditional(&format!("/universe/types/{}/", id), &[])
+                .await;
+            match res {
+                Ok(res) => Ok(Retry::Success(Some(res.data))),
+                Err(EsiApiError {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                }) => Ok(Retry::Success(None)),
+                Err(e) => Err(e),
+            }
         })
         .await?;
-        log::info!("All region orders downloaded. Calculating distances...");
 
-        let distance_cache = RwLock::new(HashMap::new());
-
-        // calculate distance to all buy orders
-        let pages: Vec<(GetMarketsRegionIdOrders200Ok, Option<usize>)> = stream::iter(pages)
-            .map(|x| async {
-                let dist_if_buy = if x.is_buy_order {
-                    let map = distance_cache.read().await;
-                    let dist = match map.get(&x.system_id) {
-                        Some(&dist) => dist,
-                        None => {
-                            drop(map);
-                            log::debug!(
-                                "Distance between origin {} and dest {} not in cache, making request...",
-                                station.system_id,
-                                x.system_id
-                            );
-                            let dist =
-                                retry::retry_smart(
-                                    || async {
-                                        let res = routes_api::get_route_origin_destination(
-                                            self.config,
-                                            GetRouteOriginDestinationParams {
-                                                destination: x.system_id,
-                                                origin: station.system_id,
-                                                avoid: None,
-                                                connections: None,
-                                                datasource: None,
-                                                flag: None,
-                                                if_none_match: None,
-                                            },
-                                        )
-                                        .await?
-                                        .entity
-                                        .unwrap();
-
-                                        Ok(Retry::Success(res.into_ok().unwrap()))
-                                    },
-                                )
-                                .await?
-                                .map(|x| Some(x.len()))
-                                .unwrap_or_else(|| {
-                                    log::warn!(
-                                        "Couldn't calculate distance between origin {} and dest {}",
-                                        station.system_id,
-                                        x.system_id
-                                    );
-                                    None
-                                });
-                            log::debug!(
-                                "Inserting distance between origin {} and dest {} into cache...",
-                                station.system_id,
-                                x.system_id
-                            );
-                            let mut map = distance_cache.write().await;
-                            map.insert(x.system_id,dist);
-                            dist
-                        }
-                    };
+        Ok(res)
+    }
 
-                    log::debug!(
-                        "Distance between origin {} and dest {} is {:?}",
-                        station.system_id,
-                        x.system_id,
-                        dist
-                    );
+    pub async fn get_orders_station(&self, station: StationIdData) -> Result<Vec<Order>> {
+        self.get_orders_area(station, &[], &[]).await
+    }
 
-                    dist
-                } else {
-                    None
-                };
+    /// Orders in the station and in `systems`, and buy orders that reach the station.
+    /// Markets of `structures` are included if the character can see them.
+    pub async fn get_orders_area(
+        &self,
+        station: StationIdData,
+        systems: &[i32],
+        structures: &[i64],
+    ) -> Result<Vec<Order>> {
+        let systems = systems.iter().copied().collect::<HashSet<_>>();
 
-                Ok((x, dist_if_buy))
+        // download all orders
+        log::info!("Downloading region orders...");
+        let pages: Vec<GetMarketsRegionIdOrders200Ok> =
+            get_all_pages(self.retry_policy, |page| async move {
+                self.get_expiring::<Vec<GetMarketsRegionIdOrders200Ok>>(
+                    &format!("/markets/{}/orders/", station.region_id),
+                    &[
+                        ("order_type", "all".to_string()),
+                        ("page", page.to_string()),
+                    ],
+                )
+                .await
             })
-            .buffer_unordered(BUFFER_UNORDERED)
-            .collect::<Vec<Result<_>>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?;
+            .await?;
+        log::info!("All region orders downloaded. Calculating distances...");
+
+        let distances = self.route_graph.jumps_from(
+            station.system_id,
+            self.route_preference,
+            self.avoid_systems,
+        );
         log::info!("All distances calculated.");
 
         let mut orders_in_station = pages
             .into_iter()
             .filter(|it| {
-                it.0.location_id == station.station_id.id
-                    || (if let Some(dist) = it.1 {
-                        it.0.is_buy_order
-                            && (match it.0.range {
-                                get_markets_region_id_orders_200_ok::Range::Station => 0,
-                                get_markets_region_id_orders_200_ok::Range::Solarsystem => 0,
-                                get_markets_region_id_orders_200_ok::Range::_1 => 1,
-                                get_markets_region_id_orders_200_ok::Range::_2 => 2,
-                                get_markets_region_id_orders_200_ok::Range::_3 => 3,
-                                get_markets_region_id_orders_200_ok::Range::_4 => 4,
-                                get_markets_region_id_orders_200_ok::Range::_5 => 5,
-                                get_markets_region_id_orders_200_ok::Range::_10 => 10,
-                                get_markets_region_id_orders_200_ok::Range::_20 => 20,
-                                get_markets_region_id_orders_200_ok::Range::_30 => 30,
-                                get_markets_region_id_orders_200_ok::Range::_40 => 40,
-                                get_markets_region_id_orders_200_ok::Range::Region => 40,
-                            }) >= dist - 1
-                    } else {
-                        false
-                    })
-            })
-            .map(|it| Order {
-                duration: it.0.duration,
-                is_buy_order: it.0.is_buy_order,
-                issued: it.0.issued,
-                location_id: it.0.location_id,
-                min_volume: it.0.min_volume,
-                order_id: it.0.order_id,
-                price: it.0.price,
-                type_id: it.0.type_id,
-                volume_remain: it.0.volume_remain,
-                volume_total: it.0.volume_total,
-            })
-            .collect::<Vec<_>>();
-
-        if station.station_id.is_citadel {
-            log::info!("Loading citadel orders...");
-            let mut orders_in_citadel = get_all_pages(|page| async move {
-                let orders = market_api::get_markets_structures_structure_id(
-                    self.config,
-                    GetMarketsStructuresStructureIdParams {
-                        structure_id: station.station_id.id,
-                        datasource: None,
-                        if_none_match: None,
-                        page: Some(page),
-                        token: None,
-                    },
-                )
-                .await?
-                .entity
-                .unwrap();
-
-                Ok(orders.into_ok().unwrap())
+                it.location_id == station.station_id.id
+                    || systems.contains(&it.system_id)
+                    || (it.is_buy_order
+                        && OrderRange::from(it.range).covers(
+                            it.location_id,
+                            station.station_id.id,
+                            distances.get(&it.system_id).copied(),
+                        ))
             })
-            .await?
-            .into_iter()
             .map(|it| Order {
                 duration: it.duration,
                 is_buy_order: it.is_buy_order,
@@ -396,11 +338,44 @@ impl<'a> EsiRequestsService<'a> {
                 volume_total: it.volume_total,
             })
             .collect::<Vec<_>>();
+
+        if station.station_id.is_citadel {
+            log::info!("Loading citadel orders...");
+            let mut orders_in_citadel = self.get_orders_structure(station.station_id.id).await?;
             log::info!("All citadel orders loaded.");
 
             orders_in_station.append(&mut orders_in_citadel);
         }
 
+        if !structures.is_empty() {
+            log::info!(
+                "Loading orders of {} public structures in the area...",
+                structures.len()
+            );
+            let orders_in_structures = stream::iter(structures.iter().copied())
+                .filter(|&id| futures::future::ready(id != station.station_id.id))
+                .map(|id| async move {
+                    match self.get_orders_structure(id).await {
+                        Ok(x) => Ok(x),
+                        // market of a public structure can still be closed to the character
+                        Err(EsiApiError {
+                            status: StatusCode::FORBIDDEN,
+                            ..
+                        }) => {
+                            log::warn!("No access to the market of structure {}", id);
+                            Ok(Vec::new())
+                        }
+                        Err(e) => Err(e),
+                    }
+                })
+                .buffer_unordered(BUFFER_UNORDERED)
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>>>()?;
+            orders_in_station.extend(orders_in_structures.into_iter().flatten());
+        }
+
         // some orders can be both regional and placed in a citadel
         // so there may be duplicates
         orders_in_station.sort_unstable_by_key(|x| x.order_id);
@@ -409,103 +384,97 @@ impl<'a> EsiRequestsService<'a> {
         Ok(orders_in_station)
     }
 
+    async fn get_orders_structure(&self, structure_id: i64) -> Result<Vec<Order>> {
+        Ok(get_all_pages(self.retry_policy, |page| async move {
+            self.get_expiring::<Vec<GetMarketsStructuresStructureId200Ok>>(
+                &format!("/markets/structures/{}/", structure_id),
+                &[("page", page.to_string())],
+            )
+            .await
+        })
+        .await?
+        .into_iter()
+        .map(|it| Order {
+            duration: it.duration,
+            is_buy_order: it.is_buy_order,
+            issued: it.issued,
+            location_id: it.location_id,
+            min_volume: it.min_volume,
+            order_id: it.order_id,
+            price: it.price,
+            type_id: it.type_id,
+            volume_remain: it.volume_remain,
+            volume_total: it.volume_total,
+        })
+        .collect())
+    }
+
+    /// Ids of public structures with a market.
+    pub async fn public_market_structures(&self) -> Result<Vec<i64>> {
+        Ok(self
+            .get_conditional::<Vec<i64>>(
+                "/universe/structures/",
+                &[("filter", "market".to_string())],
+            )
+            .await?
+            .data)
+    }
+
+    /// System of the structure, `None` if the character can't see it.
+    pub async fn structure_system(&self, structure_id: i64) -> Result<Option<i32>> {
+        let location = self.get_station_location(true, structure_id).await?;
+        Ok(location.map(|(_, system_id)| system_id))
+    }
+
+    /// History of the types in the region, downloaded in parallel.
+    /// `None` if the history of the type didn't change since it was last downloaded.
     pub async fn history(
         &self,
         item_types: &[i32],
-        station: StationIdData,
-    ) -> Result<Vec<ItemType>> {
-        let mut data = self.download_history(item_types, station).await?;
-
-        // fill blanks
-        for item in data.iter_mut() {
-            let history = std::mem::take(&mut item.history);
-            let avg = history
-                .iter()
-                .map(|x| x.average.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-            let high = history
-                .iter()
-                .map(|x| x.highest.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-            let low = history
-                .iter()
-                .map(|x| x.lowest.unwrap())
-                .map(to_not_nan)
-                .median()
-                .map(|x| *x);
-
-            // take earliest date
-            let mut dates = history
-                .into_iter()
-                .map(|x| {
-                    let date = NaiveDate::parse_from_str(x.date.as_str(), DATE_FMT).unwrap();
-                    (date, x)
-                })
-                .collect::<HashMap<_, _>>();
-            let current_date = Utc::now().naive_utc().date();
-            let past_date = current_date - Duration::days(360);
-
-            for date in past_date.iter_days() {
-                if dates.contains_key(&date) {
-                    continue;
-                }
-
-                dates.insert(
-                    date,
-                    MarketsRegionHistory {
-                        average: avg,
-                        date: date.format(DATE_FMT).to_string(),
-                        highest: high,
-                        lowest: low,
-                        order_count: 0,
-                        volume: 0,
-                    },
-                );
-
-                if date == current_date {
-                    break;
-                }
-            }
-            let new_history = dates.into_iter().sorted_by_key(|x| x.0);
-            for it in new_history {
-                item.history.push(it.1);
-            }
-        }
+        region_id: i32,
+    ) -> Result<HashMap<i32, Option<Vec<MarketsRegionHistory>>>> {
+        stream::iter(item_types)
+            .map(|&item_type| async move {
+                let history = self.get_item_type_history(region_id, item_type).await?;
+                Ok((item_type, history))
+            })
+            .buffer_unordered(BUFFER_UNORDERED)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect()
+    }
 
-        Ok(data)
+    pub fn market_data_expiry(&self, station: StationIdData) -> Option<DateTime<Utc>> {
+        let region = self
+            .responses
+            .earliest_expiry(&format!("/markets/{}/orders/", station.region_id));
+        let citadel = if station.station_id.is_citadel {
+            self.responses
+                .earliest_expiry(&format!("/markets/structures/{}/", station.station_id.id))
+        } else {
+            None
+        };
+        region.into_iter().chain(citadel).min()
     }
     async fn get_item_type_history(
         &self,
-        station: StationIdData,
+        region_id: i32,
         item_type: i32,
-        station_orders: &Mutex<HashMap<i32, Vec<Order>>>,
-    ) -> Result<Option<ItemType>> {
-        let res: Option<ItemType> = retry::retry_smart(|| async {
-            let hist_for_type: Result<_> = (|| async {
-                Ok(market_api::get_markets_region_id_history(
-                    self.config,
-                    GetMarketsRegionIdHistoryParams {
-                        region_id: station.region_id,
-                        type_id: item_type,
-                        datasource: None,
-                        if_none_match: None,
-                    },
+    ) -> Result<Option<Vec<MarketsRegionHistory>>> {
+        let res = retry::retry_smart(self.retry_policy, || async {
+            let hist_for_type: Result<Option<Vec<GetMarketsRegionIdHistory200Ok>>> = self
+                .get_if_modified(
+                    &format!("/markets/{}/history/", region_id),
+                    &[("type_id", item_type.to_string())],
                 )
-                .await?
-                .entity
-                .unwrap()
-                .into_ok()
-                .unwrap())
-            })()
-            .await;
+                .await
+                .map(|x| x.map(|x| x.data));
 
             // turn all 404 errors into empty vecs
             let hist_for_type = match hist_for_type {
-                Ok(ok) => ok,
+                Ok(Some(ok)) => ok,
+                Ok(None) => return Ok(Retry::Success(None)),
                 Err(EsiApiError {
                     status: StatusCode::NOT_FOUND,
                     ..
@@ -513,81 +482,43 @@ impl<'a> EsiRequestsService<'a> {
                 Err(e) => return Err(e),
             };
 
-            let mut dummy_empty_vec = Vec::new();
-            let item = ItemType {
-                id: item_type,
-                history: hist_for_type
-                    .into_iter()
-                    .map(|x| MarketsRegionHistory {
-                        average: Some(x.average),
-                        date: x.date,
-                        highest: Some(x.highest),
-                        lowest: Some(x.lowest),
-                        order_count: x.order_count,
-                        volume: x.volume,
-                    })
-                    .collect(),
-                orders: std::mem::take(
-                    station_orders
-                        .lock()
-                        .await
-                        .get_mut(&item_type)
-                        .unwrap_or(&mut dummy_empty_vec),
-                ),
-            };
-            Ok(Retry::Success(item))
+            let history = hist_for_type
+                .into_iter()
+                .map(|x| MarketsRegionHistory {
+                    average: Some(x.average),
+                    date: x.date,
+                    highest: Some(x.highest),
+                    lowest: Some(x.lowest),
+                    order_count: x.order_count,
+                    volume: x.volume,
+                })
+                .collect();
+            Ok(Retry::Success(Some(history)))
         })
         .await?;
         Ok(res)
     }
 
-    async fn download_history(
-        &self,
-        item_types: &[i32],
-        station: StationIdData,
-    ) -> Result<Vec<ItemType>> {
-        let station_orders = self.get_orders_station(station).await?;
-        let station_orders =
-            Mutex::new(station_orders.into_iter().into_group_map_by(|x| x.type_id));
-
-        let hists = stream::iter(item_types)
-            .map(|&item_type| {
-                let station_orders = &station_orders;
-                async move {
-                    self.get_item_type_history(station, item_type, station_orders)
-                        .await
-                }
-            })
-            .buffer_unordered(BUFFER_UNORDERED);
-        Ok(hists
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>())
-    }
-
     pub async fn get_killmail_items_frequency(
         &self,
         killmail_id: i32,
         hash: String,
     ) -> Result<Option<Killmail>> {
-        let km = retry::retry_smart(|| async {
-            let res = killmails_api::get_killmails_killmail_id_killmail_hash(
-                self.config,
-                GetKillmailsKillmailIdKillmailHashParams {
-                    killmail_hash: hash.clone(),
-                    killmail_id,
-                    datasource: None,
-                    if_none_match: None,
-                },
-            )
-            .await?
-            .entity
-            .unwrap();
-            Ok(Retry::Success(res.into_ok().unwrap()))
+        let km = retry::retry_smart(self.retry_policy, || async {
+            let res = self
+                .get_conditional::<GetKillmailsKillmailIdKillmailHashOk>(
+                    &format!("/killmails/{}/{}/", killmail_id, hash),
+                    &[],
+                )
+                .await;
+            match res {
+                Ok(res) => Ok(Retry::Success(Some(res.data))),
+                Err(EsiApiError {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                }) => Ok(Retry::Success(None)),
+                Err(e) => Err(e),
+            }
         })
         .await?;
         let km = match km {
@@ -627,31 +558,46 @@ impl<'a> EsiRequestsService<'a> {
     }
 
     pub async fn get_all_item_types(&self, region_id: i32) -> Result<Vec<i32>> {
-        let pages = get_all_pages(|page| {
-            let config = &self.config;
-            async move {
-                let types = market_api::get_markets_region_id_types(
-                    config,
-                    GetMarketsRegionIdTypesParams {
-                        region_id,
-                        datasource: None,
-                        if_none_match: None,
-                        page: Some(page),
-                    },
-                )
-                .await?
-                .entity
-                .unwrap();
-
-                Ok(types.into_ok().unwrap())
-            }
+        let pages = get_all_pages(self.retry_policy, |page| async move {
+            self.get_conditional::<Vec<i32>>(
+                &format!("/markets/{}/types/", region_id),
+                &[("page", page.to_string())],
+            )
+            .await
         })
         .await?;
 
         Ok(pages)
     }
+
+    /// Skills and standings of the character that market fees depend on.
+    pub async fn get_character_fees(&self, character_id: i32) -> Result<CharacterFees> {
+        let skills = self
+            .get_conditional::<GetCharactersCharacterIdSkillsOk>(
+                &format!("/characters/{}/skills/", character_id),
+                &[],
+            )
+            .await?
+            .data
+            .skills
+            .into_iter()
+            .map(|x| (x.skill_id, x.active_skill_level))
+            .collect();
+        let standings = self
+            .get_conditional::<Vec<GetCharactersCharacterIdStandings200Ok>>(
+                &format!("/characters/{}/standings/", character_id),
+                &[],
+            )
+            .await?
+            .data
+            .into_iter()
+            .map(|x| (x.from_id, f64::from(x.standing)))
+            .collect();
+        Ok(CharacterFees { skills, standings })
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Killmail {
     pub items: HashMap<i32, i64>,
     pub time: NaiveDateTime,
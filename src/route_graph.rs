@@ -0,0 +1,156 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::consts::{HIGH_SEC, UNWANTED_SECURITY_COST};
+
+/// Which routes are preferred, same as the `flag` of ESI route endpoint.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoutePreference {
+    #[default]
+    Shortest,
+    Secure,
+    Insecure,
+}
+
+/// Stargate connections between solar systems.
+pub struct RouteGraph {
+    jumps: HashMap<i32, Vec<i32>>,
+    security: HashMap<i32, f64>,
+}
+
+struct Visit {
+    cost: u64,
+    jumps: usize,
+}
+
+impl RouteGraph {
+    pub fn new(jumps: Vec<(i32, i32)>, security: HashMap<i32, f64>) -> Self {
+        let jumps = jumps
+            .into_iter()
+            .fold(HashMap::new(), |mut acc, (from, to)| {
+                acc.entry(from).or_insert_with(Vec::new).push(to);
+                acc
+            });
+        Self { jumps, security }
+    }
+
+    /// Number of jumps to every reachable system.
+    pub fn jumps_from(
+        &self,
+        origin: i32,
+        preference: RoutePreference,
+        avoid: &[i32],
+    ) -> HashMap<i32, usize> {
+        self.search(origin, preference, avoid)
+            .into_iter()
+            .map(|(system, visit)| (system, visit.jumps))
+            .collect()
+    }
+
+    fn jump_cost(&self, to: i32, preference: RoutePreference) -> u64 {
+        let high_sec = self.security.get(&to).map_or(false, |&x| x >= HIGH_SEC);
+        match preference {
+            RoutePreference::Secure if !high_sec => UNWANTED_SECURITY_COST,
+            RoutePreference::Insecure if high_sec => UNWANTED_SECURITY_COST,
+            _ => 1,
+        }
+    }
+
+    fn search(
+        &self,
+        origin: i32,
+        preference: RoutePreference,
+        avoid: &[i32],
+    ) -> HashMap<i32, Visit> {
+        let avoid = avoid.iter().copied().collect::<HashSet<_>>();
+        let mut visits = HashMap::new();
+        visits.insert(origin, Visit { cost: 0, jumps: 0 });
+
+        // dijkstra
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((0, origin)));
+        while let Some(Reverse((cost, system))) = queue.pop() {
+            if visits.get(&system).map_or(false, |x| x.cost < cost) {
+                continue;
+            }
+            let jumps = visits[&system].jumps;
+            for &next in self.jumps.get(&system).into_iter().flatten() {
+                if avoid.contains(&next) {
+                    continue;
+                }
+                let next_cost = cost + self.jump_cost(next, preference);
+                if visits.get(&next).map_or(true, |x| next_cost < x.cost) {
+                    visits.insert(
+                        next,
+                        Visit {
+                            cost: next_cost,
+                            jumps: jumps + 1,
+                        },
+                    );
+                    queue.push(Reverse((next_cost, next)));
+                }
+            }
+        }
+        visits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{RouteGraph, RoutePreference};
+
+    const ORIGIN: i32 = 1;
+    const LOW_SEC: i32 = 2;
+    const DESTINATION: i32 = 3;
+    const UNREACHABLE: i32 = 6;
+
+    /// Two jumps through low sec or three jumps through high sec.
+    fn graph() -> RouteGraph {
+        let gates = [(1, 2), (2, 3), (1, 4), (4, 5), (5, 3)];
+        let jumps = gates.iter().flat_map(|&(a, b)| [(a, b), (b, a)]).collect();
+        let security = HashMap::from([
+            (1, 0.9),
+            (2, 0.2),
+            (3, 0.9),
+            (4, 0.5),
+            (5, 1.0),
+            (UNREACHABLE, 0.9),
+        ]);
+        RouteGraph::new(jumps, security)
+    }
+
+    #[test]
+    fn shortest_route_counts_fewest_jumps() {
+        let jumps = graph().jumps_from(ORIGIN, RoutePreference::Shortest, &[]);
+        assert_eq!(jumps[&ORIGIN], 0);
+        assert_eq!(jumps[&LOW_SEC], 1);
+        assert_eq!(jumps[&DESTINATION], 2);
+        assert!(!jumps.contains_key(&UNREACHABLE));
+    }
+
+    #[test]
+    fn secure_route_goes_around_low_sec() {
+        let jumps = graph().jumps_from(ORIGIN, RoutePreference::Secure, &[]);
+        assert_eq!(jumps[&DESTINATION], 3);
+    }
+
+    #[test]
+    fn insecure_route_goes_through_low_sec() {
+        let jumps = graph().jumps_from(ORIGIN, RoutePreference::Insecure, &[]);
+        assert_eq!(jumps[&DESTINATION], 2);
+    }
+
+    #[test]
+    fn avoided_systems_are_not_entered() {
+        let jumps = graph().jumps_from(ORIGIN, RoutePreference::Shortest, &[LOW_SEC]);
+        assert!(!jumps.contains_key(&LOW_SEC));
+        assert_eq!(jumps[&DESTINATION], 3);
+    }
+}
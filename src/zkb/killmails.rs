@@ -5,18 +5,18 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     consts::BUFFER_UNORDERED,
-    requests::service::{EsiRequestsService, Killmail},
+    requests::{market_data_source::MarketDataSource, service::Killmail},
 };
 
 use super::zkb_requests::{ZkbRequestsService, ZkillEntity};
 
 pub struct KillmailService<'a> {
     zkb: &'a ZkbRequestsService<'a>,
-    esi: &'a EsiRequestsService<'a>,
+    esi: &'a dyn MarketDataSource,
 }
 
 impl<'a> KillmailService<'a> {
-    pub fn new(zkb: &'a ZkbRequestsService<'a>, esi: &'a EsiRequestsService<'a>) -> Self {
+    pub fn new(zkb: &'a ZkbRequestsService<'a>, esi: &'a dyn MarketDataSource) -> Self {
         Self { zkb, esi }
     }
 
@@ -1,18 +1,25 @@
 use serde::{Deserialize, Serialize};
 
-use crate::requests::retry::{retry_simple, Retry};
+use crate::{
+    error::{Error, Result},
+    requests::retry::{retry_simple, Retry, RetryPolicy},
+};
+
+pub const ZKB_API_URL: &str = "https://zkillboard.com/api";
 
 pub struct ZkbRequestsService<'a> {
     client: &'a reqwest::Client,
+    retry_policy: &'a RetryPolicy,
+    base_url: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ZkillEntity {
     pub id: u32,
     pub tp: ZkillEntityType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ZkillEntityType {
     Corporation,
     Alliance,
@@ -30,8 +37,20 @@ impl ZkillEntityType {
 }
 
 impl<'a> ZkbRequestsService<'a> {
-    pub fn new(client: &'a reqwest::Client) -> Self {
-        Self { client }
+    pub fn new(client: &'a reqwest::Client, retry_policy: &'a RetryPolicy) -> Self {
+        Self::with_base_url(client, retry_policy, ZKB_API_URL.to_string())
+    }
+
+    pub fn with_base_url(
+        client: &'a reqwest::Client,
+        retry_policy: &'a RetryPolicy,
+        base_url: String,
+    ) -> Self {
+        Self {
+            client,
+            retry_policy,
+            base_url,
+        }
     }
 
     pub async fn get_killmails(
@@ -41,13 +60,14 @@ impl<'a> ZkbRequestsService<'a> {
             tp: entity_type,
         }: &ZkillEntity,
         pages: u32,
-    ) -> Result<KillList, reqwest::Error> {
+    ) -> Result<KillList> {
         let mut kills = KillList::new();
         log::info!("Getting killmails...");
         for pg in 1..=pages {
-            let mut kills_page = retry_simple(|| async {
+            let mut kills_page = retry_simple::<_, _, _, Error>(self.retry_policy, || async {
                 let url = format!(
-                    "https://zkillboard.com/api/losses/{}/{}/page/{}/",
+                    "{}/losses/{}/{}/page/{}/",
+                    self.base_url,
                     entity_type.zkill_filter_string(),
                     entity_id,
                     pg
@@ -60,28 +80,21 @@ impl<'a> ZkbRequestsService<'a> {
                 }
 
                 let full = response.bytes().await?;
-                let ser_res = serde_json::from_slice(&full);
-
-                let kills_page = ser_res
-                    .map_err(|e| {
-                        let save_path = "cache/tmp-tst";
-                        log::error!(
-                            "Errorneous url: {}. Zkill server response saved to: {}",
-                            url,
-                            save_path
-                        );
-                        std::fs::write(save_path, &full).unwrap();
+                let kills_page = serde_json::from_slice(&full).map_err(|e| {
+                    log::error!(
+                        "Errorneous url: {}. Couldn't parse zkill response: {}",
+                        url,
                         e
-                    })
-                    .unwrap();
+                    );
+                    e
+                })?;
 
                 // zkillboard allows only one request per second
                 tokio::time::sleep(std::time::Duration::from_secs_f32(1.)).await;
 
                 Ok(Retry::Success(kills_page))
             })
-            .await?
-            .unwrap();
+            .await?;
             kills.append(&mut kills_page);
         }
         log::info!("{} page of killmails downloaded", pages);
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use serde_json::json;
+use unusable_eve_tradeworks_lib::{
+    config::Config,
+    consts::DATE_FMT,
+    good_items::{
+        report::FilterReport, sell_buy::get_good_items_sell_buy,
+        sell_sell::get_good_items_sell_sell,
+    },
+    item_type::{
+        MarketData, MarketsRegionHistory, Order, SourceHub, SystemMarketsItemData, TypeDescription,
+    },
+    market_data::{load_market_data, route_pairs, MarketLocation},
+    market_store::{last_downtime, MarketStore},
+    requests::{
+        fixture::{FixtureMarketDataSource, MarketSnapshot},
+        market_data_source::MarketDataSource,
+    },
+    MarketScope, StationId, StationIdData,
+};
+
+const SOURCE: StationIdData = StationIdData {
+    station_id: StationId {
+        is_citadel: false,
+        id: 60003760,
+    },
+    system_id: 30000142,
+    region_id: 10000002,
+};
+const DESTINATION: StationIdData = StationIdData {
+    station_id: StationId {
+        is_citadel: false,
+        id: 60008494,
+    },
+    system_id: 30002187,
+    region_id: 10000043,
+};
+
+/// Bought cheap at the source and sold expensive at the destination.
+const PROFITABLE: i32 = 34;
+/// Cheaper at the destination than at the source.
+const UNPROFITABLE: i32 = 35;
+
+fn order(
+    order_id: i64,
+    type_id: i32,
+    location_id: i64,
+    is_buy_order: bool,
+    price: f64,
+    volume: i32,
+) -> Order {
+    Order {
+        duration: 90,
+        is_buy_order,
+        issued: "2022-01-01T00:00:00Z".to_string(),
+        location_id,
+        min_volume: 1,
+        order_id,
+        price,
+        type_id,
+        volume_remain: volume,
+        volume_total: volume,
+    }
+}
+
+/// Ten days up to the last published one with the same price and volume.
+fn history(average: f64, volume: i64) -> Vec<MarketsRegionHistory> {
+    let last_published = last_downtime().naive_utc().date() - Duration::days(1);
+    (0..10)
+        .map(|x| MarketsRegionHistory {
+            average: Some(average),
+            date: (last_published - Duration::days(x))
+                .format(DATE_FMT)
+                .to_string(),
+            highest: Some(average),
+            lowest: Some(average),
+            order_count: 10,
+            volume,
+        })
+        .collect()
+}
+
+fn description(type_id: i32, name: &str) -> TypeDescription {
+    TypeDescription {
+        capacity: None,
+        description: String::new(),
+        graphic_id: None,
+        group_id: 18,
+        icon_id: None,
+        market_group_id: Some(1857),
+        mass: None,
+        name: name.to_string(),
+        packaged_volume: Some(0.01),
+        portion_size: Some(1),
+        published: true,
+        radius: None,
+        type_id,
+        volume: Some(0.01),
+    }
+}
+
+fn snapshot() -> MarketSnapshot {
+    let source = SOURCE.station_id.id;
+    let destination = DESTINATION.station_id.id;
+    MarketSnapshot {
+        type_descriptions: HashMap::from([
+            (PROFITABLE, Some(description(PROFITABLE, "Tritanium"))),
+            (UNPROFITABLE, Some(description(UNPROFITABLE, "Pyerite"))),
+        ]),
+        orders: HashMap::from([
+            (
+                source,
+                vec![
+                    order(1, PROFITABLE, source, false, 5., 1000),
+                    order(2, UNPROFITABLE, source, false, 10., 1000),
+                ],
+            ),
+            (
+                destination,
+                vec![
+                    order(3, PROFITABLE, destination, false, 10., 10),
+                    order(4, PROFITABLE, destination, true, 8., 100),
+                    order(5, UNPROFITABLE, destination, false, 6., 10),
+                    order(6, UNPROFITABLE, destination, true, 5., 100),
+                ],
+            ),
+        ]),
+        history: HashMap::from([
+            (
+                SOURCE.region_id,
+                HashMap::from([
+                    (PROFITABLE, history(5., 500)),
+                    (UNPROFITABLE, history(10., 500)),
+                ]),
+            ),
+            (
+                DESTINATION.region_id,
+                HashMap::from([
+                    (PROFITABLE, history(10., 50)),
+                    (UNPROFITABLE, history(6., 50)),
+                ]),
+            ),
+        ]),
+        ..Default::default()
+    }
+}
+
+fn config() -> Config {
+    serde_json::from_value(json!({
+        "days_average": 10,
+        "margin_cutoff": 0.05,
+        "sales_tax": 0.05,
+        "broker_fee_source": 0.03,
+        "broker_fee_destination": 0.01,
+        "items_take": 10,
+        "zkill_entity": { "id": 1, "tp": "Alliance" },
+        "sell_sell": {
+            "freight_cost_iskm3": 0,
+            "freight_cost_collateral_percent": 0,
+            "rcmnd_fill_days": 3,
+            "max_filled_for_days_cutoff": 2,
+            "min_src_volume": 0,
+            "min_dst_volume": 0.1,
+            "sell_sell_zkb": { "min_dst_zkb_lost_volume": 1, "zkb_download_pages": 1 }
+        },
+        "sell_buy": { "cargo_capacity": 50000 }
+    }))
+    .unwrap()
+}
+
+fn location(station: StationIdData) -> MarketLocation {
+    MarketLocation {
+        station,
+        scope: MarketScope::Station,
+        systems: Vec::new(),
+    }
+}
+
+fn key(station: StationIdData) -> (i64, MarketScope) {
+    (station.station_id.id, MarketScope::Station)
+}
+
+async fn market_data(
+    snapshot: MarketSnapshot,
+) -> HashMap<(i64, MarketScope), HashMap<i32, MarketData>> {
+    let market = FixtureMarketDataSource::new(snapshot);
+    let store = MarketStore::open_in_memory().unwrap();
+    load_market_data(
+        &market,
+        &store,
+        &[PROFITABLE, UNPROFITABLE],
+        &[location(SOURCE), location(DESTINATION)],
+        false,
+        false,
+        None,
+    )
+    .await
+    .unwrap()
+}
+
+fn source_hub(config: &Config) -> SourceHub {
+    SourceHub {
+        name: "Jita".to_string(),
+        broker_fee: config.broker_fee_source,
+        freight_cost_iskm3: config.sell_sell.freight_cost_iskm3,
+        freight_cost_collateral_percent: config.sell_sell.freight_cost_collateral_percent,
+    }
+}
+
+/// Pairs made the same way as for a route from Jita to the destination.
+async fn pairs(config: &Config, report: &mut FilterReport) -> Vec<SystemMarketsItemData> {
+    let market_data = market_data(snapshot()).await;
+    route_pairs(
+        &[(&market_data[&key(SOURCE)], source_hub(config))],
+        &market_data[&key(DESTINATION)],
+        &snapshot().type_descriptions,
+        None,
+        report,
+    )
+}
+
+#[tokio::test]
+async fn sell_sell_recommends_profitable_item() {
+    let config = config();
+    let mut report = FilterReport::default();
+    let pairs = pairs(&config, &mut report).await;
+    let items = get_good_items_sell_sell(pairs, &config, false, &mut report);
+
+    assert_eq!(items.len(), 1);
+    let item = &items[0];
+    assert_eq!(item.market.desc.type_id, PROFITABLE);
+    assert_eq!(item.source, "Jita");
+    // three days of destination volume
+    assert_eq!(item.recommend_buy, 150);
+    assert_eq!(item.src_buy_price, 5.);
+    assert_eq!(item.dest_min_sell_price, 10.);
+    assert!(report.rejections.iter().any(|x| x.type_id == UNPROFITABLE));
+}
+
+#[tokio::test]
+async fn sell_buy_recommends_filling_buy_orders() {
+    let config = config();
+    let mut report = FilterReport::default();
+    let pairs = pairs(&config, &mut report).await;
+    let items = get_good_items_sell_buy(pairs, &config, false, &mut report);
+
+    assert_eq!(items.items.len(), 1);
+    let item = &items.items[0];
+    assert_eq!(item.market.desc.type_id, PROFITABLE);
+    // the whole buy order
+    assert_eq!(item.recommend_buy, 100);
+    assert_eq!(item.src_buy_price, 5.);
+    assert_eq!(item.dest_min_sell_price, 8.);
+    assert!(report.rejections.iter().any(|x| x.type_id == UNPROFITABLE));
+}
+
+#[tokio::test]
+async fn types_without_description_or_source_history_are_reported() {
+    let market_data = market_data(snapshot()).await;
+    let mut source = market_data[&key(SOURCE)].clone();
+    source.remove(&PROFITABLE);
+    let mut descriptions = snapshot().type_descriptions;
+    descriptions.remove(&UNPROFITABLE);
+
+    let mut report = FilterReport::default();
+    let pairs = route_pairs(
+        &[(&source, source_hub(&config()))],
+        &market_data[&key(DESTINATION)],
+        &descriptions,
+        None,
+        &mut report,
+    );
+
+    assert!(pairs.is_empty());
+    let filter = |type_id| {
+        report
+            .rejections
+            .iter()
+            .find(|x| x.type_id == type_id)
+            .map(|x| x.filter)
+    };
+    assert_eq!(filter(PROFITABLE), Some("missing_source_history"));
+    assert_eq!(filter(UNPROFITABLE), Some("missing_description"));
+}
+
+#[tokio::test]
+async fn area_orders_are_kept_per_scope() {
+    let destination = DESTINATION.station_id.id;
+    let snapshot = MarketSnapshot {
+        area_orders: HashMap::from([(
+            destination,
+            HashMap::from([
+                (
+                    MarketScope::System,
+                    vec![order(1, PROFITABLE, destination, false, 10., 10)],
+                ),
+                (
+                    MarketScope::Region,
+                    vec![order(2, PROFITABLE, destination, false, 9., 10)],
+                ),
+            ]),
+        )]),
+        ..Default::default()
+    };
+    let market = FixtureMarketDataSource::new(snapshot);
+
+    let orders = |scope| market.get_orders_area(DESTINATION, scope, &[], &[]);
+    assert_eq!(orders(MarketScope::System).await.unwrap()[0].order_id, 1);
+    assert_eq!(orders(MarketScope::Region).await.unwrap()[0].order_id, 2);
+    assert!(orders(MarketScope::Constellation).await.unwrap().is_empty());
+}